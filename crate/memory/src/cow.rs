@@ -17,8 +17,10 @@
 //!
 //! A frame can have write and read reference at the same time,
 //! so we need to maintain the count of write and read reference.
-//! When page fault occurs, if the read reference count is 0 and the write reference count is 1，
+//! When page fault occurs, if the frame has only one reference in total,
 //! The copy process should be skipped and the entry is mark as writable directly.
+//! For the same reason, `unshare()` on the CowExt gives the frame back to the last reference,
+//! so the memory handlers can tell whether the frame should be deallocated.
 
 use super::paging::*;
 use super::*;
@@ -49,18 +51,45 @@ impl<T: PageTable> CowExt<T> {
     **  @param  target: VirtAddr     the target physics address
     **  @param  writable: bool       if it is true, set the page as writable and shared
     **                               else set the page as readonly and shared
-    **  @retval none
+    **  @retval &mut Entry           the page table entry of the mapped page
     */
-    pub fn map_to_shared(&mut self, addr: VirtAddr, target: PhysAddr, writable: bool) {
-        let entry = self.page_table.map(addr, target);
-        entry.set_writable(false);
-        entry.set_shared(writable);
-        entry.update();
+    pub fn map_to_shared(&mut self, addr: VirtAddr, target: PhysAddr, writable: bool) -> &mut Entry {
         let frame = target / PAGE_SIZE;
         match writable {
             true => self.rc_map.write_increase(&frame),
             false => self.rc_map.read_increase(&frame),
         }
+        let entry = self.page_table.map(addr, target);
+        entry.set_writable(false);
+        entry.set_shared(writable);
+        entry.update();
+        entry
+    }
+    /*
+    **  @brief  share the page mapped at the virtual address,
+    **          so that it can be mapped to other page tables by `map_to_shared`
+    **          an unshared page becomes shared as its current writable state
+    **  @param  addr: VirtAddr       the virual address of the page
+    **  @retval Option<PhysAddr>     the physics address of the shared frame,
+    **                               None if the page is not present
+    */
+    pub fn share(&mut self, addr: VirtAddr) -> Option<PhysAddr> {
+        let entry = self.page_table.get_entry(addr)?;
+        if !entry.present() {
+            return None;
+        }
+        if !is_shared(entry) {
+            let writable = entry.writable();
+            entry.set_writable(false);
+            entry.set_shared(writable);
+            entry.update();
+            let frame = entry.target() / PAGE_SIZE;
+            match writable {
+                true => self.rc_map.write_increase(&frame),
+                false => self.rc_map.read_increase(&frame),
+            }
+        }
+        Some(entry.target())
     }
     /*
     **  @brief  unmap a virual address from physics address
@@ -72,10 +101,8 @@ impl<T: PageTable> CowExt<T> {
         let entry = self.page_table.get_entry(addr)
             .expect("entry not exist");
         let frame = entry.target() / PAGE_SIZE;
-        if entry.readonly_shared() {
-            self.rc_map.read_decrease(&frame);
-        } else if entry.writable_shared() {
-            self.rc_map.write_decrease(&frame);
+        if entry.readonly_shared() || entry.writable_shared() {
+            self.rc_map.decrease(&frame, entry.writable_shared());
         }
         self.page_table.unmap(addr);
     }
@@ -95,29 +122,92 @@ impl<T: PageTable> CowExt<T> {
             return false;
         }
         let entry = entry.unwrap();
-        if !entry.readonly_shared() && !entry.writable_shared() {
+        // a readonly shared page is accurately readonly
+        if !is_shared(entry) || entry.readonly_shared() {
             return false;
         }
         let frame = entry.target() / PAGE_SIZE;
-        if self.rc_map.read_count(&frame) == 0 && self.rc_map.write_count(&frame) == 1 {
+        if self.rc_map.count(&frame) == 1 {
             entry.clear_shared();
             entry.set_writable(true);
             entry.update();
-            self.rc_map.write_decrease(&frame);
+            self.rc_map.decrease(&frame, true);
             return true;
         }
+        let user = entry.user();
+        let execute = entry.execute();
         use core::mem::uninitialized;
         let mut temp_data: [u8; PAGE_SIZE] = unsafe { uninitialized() };
         temp_data[..].copy_from_slice(self.get_page_slice_mut(addr));
 
         self.unmap_shared(addr);
-        self.map(addr, alloc_frame());
+        let entry = self.page_table.map(addr, alloc_frame());
+        entry.clear_shared();
+        entry.set_user(user);
+        entry.set_execute(execute);
+        entry.update();
 
         self.get_page_slice_mut(addr).copy_from_slice(&temp_data[..]);
         true
     }
 }
 
+impl<T: PageTable> PageTable for CowExt<T> {
+    fn map(&mut self, addr: VirtAddr, target: PhysAddr) -> &mut Entry {
+        self.page_table.map(addr, target)
+    }
+    fn unmap(&mut self, addr: VirtAddr) {
+        let shared = self.page_table.get_entry(addr).map_or(false, |entry| is_shared(entry));
+        match shared {
+            true => self.unmap_shared(addr),
+            false => self.page_table.unmap(addr),
+        }
+    }
+    fn get_entry(&mut self, addr: VirtAddr) -> Option<&mut Entry> {
+        self.page_table.get_entry(addr)
+    }
+    /// The last reference of a shared frame owns the frame again,
+    /// so the entry is only left shared if the frame is still used by others.
+    fn unshare(&mut self, addr: VirtAddr) {
+        let CowExt { page_table, rc_map } = self;
+        let entry = match page_table.get_entry(addr) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if is_shared(entry) {
+            let frame = entry.target() / PAGE_SIZE;
+            if rc_map.count(&frame) == 1 {
+                let writable = entry.writable_shared();
+                rc_map.decrease(&frame, writable);
+                entry.clear_shared();
+                entry.set_writable(writable);
+                entry.update();
+            }
+        }
+    }
+    fn get_page_slice_mut<'a>(&mut self, addr: VirtAddr) -> &'a mut [u8] {
+        self.page_table.get_page_slice_mut(addr)
+    }
+    fn read(&mut self, addr: VirtAddr) -> u8 {
+        self.page_table.read(addr)
+    }
+    fn write(&mut self, addr: VirtAddr, data: u8) {
+        self.page_table.write(addr, data)
+    }
+}
+
+impl<T: PageTableExt> PageTableExt for CowExt<T> {}
+
+/*
+**  @brief  test whether the page of the entry is shared
+**  @param  entry: &Entry        the page table entry to test
+**  @retval bool                 whether the page is present and shared
+*/
+fn is_shared(entry: &Entry) -> bool {
+    // the shared bits may mean other things for a page not present
+    entry.present() && (entry.readonly_shared() || entry.writable_shared())
+}
+
 impl<T: PageTable> Deref for CowExt<T> {
     type Target = T;
 
@@ -167,12 +257,12 @@ impl FrameRcMap {
         self.map().insert(frame.clone(), (r + 1, w));
     }
     /*
-    **  @brief  decrease the read reference count of the frame
-    **  @param  frame: &Frame        the frame to decrease the read reference count
-    **  @retval none
+    **  @brief  get the total reference count of the frame
+    **  @param  frame: &Frame        the frame to get the reference count
+    **  @retval u16                  the read and write reference count
     */
-    fn read_decrease(&mut self, frame: &Frame) {
-        self.map().get_mut(frame).unwrap().0 -= 1;
+    fn count(&mut self, frame: &Frame) -> u16 {
+        self.read_count(frame) + self.write_count(frame)
     }
     /*
     **  @brief  increase the write reference count of the frame
@@ -184,12 +274,24 @@ impl FrameRcMap {
         self.map().insert(frame.clone(), (r, w + 1));
     }
     /*
-    **  @brief  decrease the write reference count of the frame
-    **  @param  frame: &Frame        the frame to decrease the write reference count
+    **  @brief  decrease a reference count of the frame
+    **          the sharing kind of an entry may be changed after it is shared,
+    **          so the other count is decreased if the one of `writable` is 0
+    **  @param  frame: &Frame        the frame to decrease the reference count
+    **  @param  writable: bool       decrease the write reference count if it is true
+    **                               else decrease the read reference count
     **  @retval none
     */
-    fn write_decrease(&mut self, frame: &Frame) {
-        self.map().get_mut(frame).unwrap().1 -= 1;
+    fn decrease(&mut self, frame: &Frame, writable: bool) {
+        let count = self.map().get_mut(frame).unwrap();
+        if (writable && count.1 > 0) || count.0 == 0 {
+            count.1 -= 1;
+        } else {
+            count.0 -= 1;
+        }
+        if *count == (0, 0) {
+            self.map().remove(frame);
+        }
     }
     /*
     **  @brief  get the internal btree map, lazily initialize the btree map if it is not present
@@ -229,6 +331,28 @@ pub mod test {
         test_with(&mut pt);
     }
 
+    #[test]
+    fn share() {
+        let mut pt = CowExt::new(MockPageTable::new());
+        let target = 0x0;
+        let frame = 0x0;
+
+        pt.map(0x1000, target);
+        assert_eq!(pt.share(0x1000), Some(target));
+        pt.map_to_shared(0x2000, target, true);
+        assert_eq!(pt.rc_map.write_count(&frame), 2);
+        assert!(!pt.get_entry(0x1000).unwrap().writable());
+
+        pt.unmap(0x2000);
+        assert_eq!(pt.rc_map.write_count(&frame), 1);
+        assert!(pt.get_entry(0x1000).unwrap().writable_shared());
+        pt.unshare(0x1000);
+        let entry = pt.get_entry(0x1000).unwrap();
+        assert!(entry.writable() && !entry.writable_shared(),
+                "The last reference should own the frame again.");
+        assert_eq!(pt.rc_map.count(&frame), 0);
+    }
+
     pub fn test_with(pt: &mut CowExt<impl PageTable>) {
         let target = 0x0;
         let frame = 0x0;
@@ -2,7 +2,6 @@ use super::*;
 
 #[derive(Debug, Clone)]
 pub struct ByFrame<T: FrameAllocator> {
-    flags: MemoryAttr,
     allocator: T,
 }
 
@@ -11,14 +10,18 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
         Box::new(self.clone())
     }
 
-    fn map(&self, pt: &mut PageTable, addr: VirtAddr) {
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
         let target = self.allocator.alloc().expect("failed to allocate frame");
-        self.flags.apply(pt.map(addr, target));
+        attr.apply(pt.map(addr, target));
     }
 
     fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
-        let target = pt.get_entry(addr).expect("fail to get entry").target();
-        self.allocator.dealloc(target);
+        pt.unshare(addr);
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        // the frame of a shared page is still used by other page tables
+        if !entry.readonly_shared() && !entry.writable_shared() {
+            self.allocator.dealloc(entry.target());
+        }
         pt.unmap(addr);
     }
 
@@ -28,7 +31,7 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
 }
 
 impl<T: FrameAllocator> ByFrame<T> {
-    pub fn new(flags: MemoryAttr, allocator: T) -> Self {
-        ByFrame { flags, allocator }
+    pub fn new(allocator: T) -> Self {
+        ByFrame { allocator }
     }
 }
\ No newline at end of file
@@ -2,7 +2,6 @@ use super::*;
 
 #[derive(Debug, Clone)]
 pub struct Delay<T: FrameAllocator> {
-    flags: MemoryAttr,
     allocator: T,
 }
 
@@ -11,18 +10,26 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         Box::new(self.clone())
     }
 
-    fn map(&self, pt: &mut PageTable, addr: VirtAddr) {
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
         let entry = pt.map(addr, 0);
+        attr.apply(entry);
         entry.set_present(false);
         entry.update();
     }
 
     fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
+        pt.unshare(addr);
         let entry = pt.get_entry(addr).expect("failed to get entry");
-        if entry.present() {
+        // the frame of a shared page is still used by other page tables
+        if entry.present() && !entry.readonly_shared() && !entry.writable_shared() {
             self.allocator.dealloc(entry.target());
-            pt.unmap(addr);
         }
+        // PageTable::unmap requires the page to be present,
+        // and the swapped bit may be taken as shared for a present page
+        entry.set_swapped(false);
+        entry.set_present(true);
+        entry.update();
+        pt.unmap(addr);
     }
 
     fn page_fault_handler(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
@@ -33,13 +40,14 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         }
         let frame = self.allocator.alloc().expect("failed to alloc frame");
         entry.set_target(frame);
-        self.flags.apply(entry);
+        entry.set_present(true);
+        entry.update();
         true
     }
 }
 
 impl<T: FrameAllocator> Delay<T> {
-    pub fn new(flags: MemoryAttr, allocator: T) -> Self {
-        Delay { flags, allocator }
+    pub fn new(allocator: T) -> Self {
+        Delay { allocator }
     }
 }
\ No newline at end of file
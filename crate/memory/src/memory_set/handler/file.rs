@@ -0,0 +1,112 @@
+use super::*;
+
+/// A file which can be mapped by `File`
+pub trait Read: Clone + 'static {
+    /// Read at most `buf.len()` bytes at `offset` of the file.
+    /// Return the number of bytes read, which is less than `buf.len()` at the end of the file.
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, ()>;
+}
+
+/// Map `[file_start, file_end)` of a file at `mem_start`, and fill the rest of the area with zeros.
+/// A page is loaded from the file on the first access.
+#[derive(Clone)]
+pub struct File<F: Read, T: FrameAllocator> {
+    file: F,
+    mem_start: VirtAddr,
+    file_start: usize,
+    file_end: usize,
+    allocator: T,
+}
+
+impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let entry = pt.map(addr, 0);
+        attr.apply(entry);
+        entry.set_present(false);
+        entry.update();
+    }
+
+    fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
+        pt.unshare(addr);
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        // the frame of a shared page is still used by other page tables
+        if entry.present() && !entry.readonly_shared() && !entry.writable_shared() {
+            self.allocator.dealloc(entry.target());
+        }
+        // PageTable::unmap requires the page to be present,
+        // and the swapped bit may be taken as shared for a present page
+        entry.set_swapped(false);
+        entry.set_present(true);
+        entry.update();
+        pt.unmap(addr);
+    }
+
+    fn page_fault_handler(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        match self.load_page(addr) {
+            Ok(Some(data)) => self.page_fault_handler_with(pt, addr, &data),
+            _ => false,
+        }
+    }
+
+    fn load_page(&self, addr: VirtAddr) -> Result<Option<Vec<u8>>, ()> {
+        let addr = addr & !(PAGE_SIZE - 1);
+        let mut data = alloc::vec![0u8; PAGE_SIZE];
+        self.fill_page(&mut data, addr)?;
+        Ok(Some(data))
+    }
+
+    fn page_fault_handler_with(&self, pt: &mut PageTable, addr: VirtAddr, data: &[u8]) -> bool {
+        let addr = addr & !(PAGE_SIZE - 1);
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            // not a delay case
+            return false;
+        }
+        let frame = self.allocator.alloc().expect("failed to alloc frame");
+        let writable = entry.writable();
+        entry.set_target(frame);
+        entry.set_present(true);
+        // the page is filled through its virtual address, so it's writable until then
+        entry.set_writable(true);
+        entry.update();
+        pt.get_page_slice_mut(addr).copy_from_slice(data);
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_writable(writable);
+        entry.update();
+        true
+    }
+}
+
+impl<F: Read, T: FrameAllocator> File<F, T> {
+    pub fn new(file: F, mem_start: VirtAddr, file_start: usize, file_end: usize, allocator: T) -> Self {
+        assert!(file_start <= file_end, "invalid file range");
+        File { file, mem_start, file_start, file_end, allocator }
+    }
+
+    /// Fill the page at `addr` with the file data
+    fn fill_page(&self, data: &mut [u8], addr: VirtAddr) -> Result<(), ()> {
+        data.iter_mut().for_each(|x| *x = 0);
+        let begin = addr.max(self.mem_start);
+        let end = (addr + PAGE_SIZE).min(self.mem_start + (self.file_end - self.file_start));
+        if begin < end {
+            let offset = begin - self.mem_start + self.file_start;
+            self.file.read_at(offset, &mut data[begin - addr..end - addr])?;
+        }
+        Ok(())
+    }
+}
+
+impl<F: Read, T: FrameAllocator> Debug for File<F, T> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_struct("File")
+            .field("mem_start", &self.mem_start)
+            .field("file_start", &self.file_start)
+            .field("file_end", &self.file_end)
+            .field("allocator", &self.allocator)
+            .finish()
+    }
+}
@@ -3,9 +3,35 @@ use super::*;
 // here may be a interesting part for lab
 pub trait MemoryHandler: Debug + 'static {
     fn box_clone(&self) -> Box<MemoryHandler>;
-    fn map(&self, pt: &mut PageTable, addr: VirtAddr);
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr);
     fn unmap(&self, pt: &mut PageTable, addr: VirtAddr);
     fn page_fault_handler(&self, pt: &mut PageTable, addr: VirtAddr) -> bool;
+
+    /// Load the data of the page at `addr` for its page fault, e.g. from a file.
+    /// It's called without the memory set locked, since loading may take long or fault itself,
+    /// and the data is passed to `page_fault_handler_with`.
+    /// Return `Ok(None)` if the handler doesn't load data, or `Err` if loading failed.
+    fn load_page(&self, _addr: VirtAddr) -> Result<Option<Vec<u8>>, ()> {
+        Ok(None)
+    }
+
+    /// Handle the page fault at `addr` with the `data` returned by `load_page`
+    fn page_fault_handler_with(&self, pt: &mut PageTable, addr: VirtAddr, _data: &[u8]) -> bool {
+        self.page_fault_handler(pt, addr)
+    }
+
+    /// Apply the changed `attr` to the mapped page at `addr`.
+    /// A page which is not present yet stays not present.
+    fn protect(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        let present = entry.present();
+        if present && (entry.readonly_shared() || entry.writable_shared()) {
+            entry.set_shared(!attr.readonly);
+        }
+        attr.apply(entry);
+        entry.set_present(present);
+        entry.update();
+    }
 }
 
 impl Clone for Box<MemoryHandler> {
@@ -22,8 +48,10 @@ pub trait FrameAllocator: Debug + Clone + 'static {
 mod linear;
 mod byframe;
 mod delay;
+mod file;
 //mod swap;
 
 pub use self::linear::Linear;
 pub use self::byframe::ByFrame;
-pub use self::delay::Delay;
\ No newline at end of file
+pub use self::delay::Delay;
+pub use self::file::{File, Read};
\ No newline at end of file
@@ -1,10 +1,12 @@
 //! memory set, area
 //! and the inactive page table
 
-use alloc::{vec::Vec, boxed::Box};
+use alloc::{vec::Vec, boxed::Box, string::String};
 use core::fmt::{Debug, Error, Formatter};
+use core::mem::size_of;
 use super::*;
 use crate::paging::*;
+use crate::cow::CowExt;
 use self::handler::MemoryHandler;
 
 pub mod handler;
@@ -15,11 +17,21 @@ pub mod handler;
 pub struct MemoryArea {
     start_addr: VirtAddr,
     end_addr: VirtAddr,
+    attr: MemoryAttr,
     handler: Box<MemoryHandler>,
     name: &'static str,
 }
 
 impl MemoryArea {
+    pub fn get_start_addr(&self) -> VirtAddr {
+        self.start_addr
+    }
+    pub fn get_end_addr(&self) -> VirtAddr {
+        self.end_addr
+    }
+    pub fn get_handler(&self) -> &MemoryHandler {
+        &*self.handler
+    }
     /*
     **  @brief  get slice of the content in the memory area
     **  @retval &[u8]                the slice of the content in the memory area
@@ -43,25 +55,61 @@ impl MemoryArea {
         addr >= self.start_addr && addr < self.end_addr
     }
     /*
+    **  @brief  test whether the user can read the address range [`start`, `end`) in the memory area
+    **  @param  start: VirtAddr      the beginning of the range
+    **  @param  end: VirtAddr        the end of the range
+    **  @retval bool                 whether the range is user readable
+    */
+    fn check_user_read(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        self.attr.user && !self.attr.hide && start >= self.start_addr && end <= self.end_addr
+    }
+    /*
+    **  @brief  test whether the user can write the address range [`start`, `end`) in the memory area
+    **  @param  start: VirtAddr      the beginning of the range
+    **  @param  end: VirtAddr        the end of the range
+    **  @retval bool                 whether the range is user writable
+    */
+    fn check_user_write(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        !self.attr.readonly && self.check_user_read(start, end)
+    }
+    /*
     **  @brief  test whether the memory area is overlap with another memory area
     **  @param  other: &MemoryArea   another memory area to test
     **  @retval bool                 whether the memory area is overlap with another memory area
     */
     fn is_overlap_with(&self, other: &MemoryArea) -> bool {
+        self.is_overlap_with_range(other.start_addr, other.end_addr)
+    }
+    /*
+    **  @brief  test whether the memory area is overlap with the range [`start_addr`, `end_addr`)
+    **  @param  start_addr: VirtAddr the beginning of the range
+    **  @param  end_addr: VirtAddr   the end of the range
+    **  @retval bool                 whether the memory area is overlap with the range
+    */
+    fn is_overlap_with_range(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
         let p0 = Page::of_addr(self.start_addr);
         let p1 = Page::of_addr(self.end_addr - 1) + 1;
-        let p2 = Page::of_addr(other.start_addr);
-        let p3 = Page::of_addr(other.end_addr - 1) + 1;
+        let p2 = Page::of_addr(start_addr);
+        let p3 = Page::of_addr(end_addr - 1) + 1;
         !(p1 <= p2 || p0 >= p3)
     }
     /*
+    **  @brief  test whether the memory area is inside the range [`start_addr`, `end_addr`)
+    **  @param  start_addr: VirtAddr the beginning of the range
+    **  @param  end_addr: VirtAddr   the end of the range
+    **  @retval bool                 whether the memory area is inside the range
+    */
+    fn is_inside(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        start_addr <= self.start_addr && self.end_addr <= end_addr
+    }
+    /*
     **  @brief  map the memory area to the physice address in a page table
     **  @param  pt: &mut T::Active   the page table to use
     **  @retval none
     */
     fn map(&self, pt: &mut PageTable) {
         for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.map(pt, page.start_address());
+            self.handler.map(pt, page.start_address(), &self.attr);
         }
     }
     /*
@@ -74,6 +122,16 @@ impl MemoryArea {
             self.handler.unmap(pt, page.start_address());
         }
     }
+    /*
+    **  @brief  apply the attribute of the memory area to its mapped pages in a page table
+    **  @param  pt: &mut T::Active   the page table to use
+    **  @retval none
+    */
+    fn protect(&self, pt: &mut PageTable) {
+        for page in Page::range_of(self.start_addr, self.end_addr) {
+            self.handler.protect(pt, page.start_address(), &self.attr);
+        }
+    }
 }
 
 /// The attributes of the memory
@@ -83,6 +141,7 @@ pub struct MemoryAttr {
     readonly: bool,
     execute: bool,
     mmio: u8,
+    hide: bool,
 }
 
 impl MemoryAttr {
@@ -111,6 +170,15 @@ impl MemoryAttr {
         self
     }
     /*
+    **  @brief  set the memory attribute's hide bit
+    **          the pages of a hidden user area can't be accessed by the user at all
+    **  @retval MemoryAttr           the memory attribute itself
+    */
+    pub fn hide(mut self) -> Self {
+        self.hide = true;
+        self
+    }
+    /*
     **  @brief  set the MMIO type
     **  @retval MemoryAttr           the memory attribute itself
     */
@@ -125,9 +193,11 @@ impl MemoryAttr {
     **  @retval none
     */
     pub fn apply(&self, entry: &mut Entry) {
+        // a shared page stays readonly until it is copied on write
+        let shared = entry.readonly_shared() || entry.writable_shared();
         entry.set_present(true);
-        entry.set_user(self.user);
-        entry.set_writable(!self.readonly);
+        entry.set_user(self.user && !self.hide);
+        entry.set_writable(!self.readonly && !shared);
         entry.set_execute(self.execute);
         entry.set_mmio(self.mmio);
         entry.update();
@@ -167,13 +237,99 @@ impl<T: InactivePageTable> MemorySet<T> {
         self.areas.iter().find(|area| area.contains(addr))
     }
     /*
+    **  @brief  check that the user can read `count` elements of `S` at `ptr`
+    **  @param  ptr: *const S        the beginning of the array
+    **  @param  count: usize         the number of elements
+    **  @retval VMResult<()>         Ok if the whole array lies in user readable areas
+    */
+    pub fn check_read_array<S>(&self, ptr: *const S, count: usize) -> VMResult<()> {
+        self.check_range(ptr as usize, count, size_of::<S>(), MemoryArea::check_user_read)
+    }
+    /*
+    **  @brief  check that the user can write `count` elements of `S` at `ptr`
+    **  @param  ptr: *mut S          the beginning of the array
+    **  @param  count: usize         the number of elements
+    **  @retval VMResult<()>         Ok if the whole array lies in user writable areas
+    */
+    pub fn check_write_array<S>(&self, ptr: *mut S, count: usize) -> VMResult<()> {
+        self.check_range(ptr as usize, count, size_of::<S>(), MemoryArea::check_user_write)
+    }
+    /*
+    **  @brief  check that the user can read a `S` at `ptr`
+    **  @param  ptr: *const S        the pointer to check
+    **  @retval VMResult<()>         Ok if the pointer is user readable
+    */
+    pub fn check_read_ptr<S>(&self, ptr: *const S) -> VMResult<()> {
+        self.check_read_array(ptr, 1)
+    }
+    /*
+    **  @brief  check that the user can write a `S` at `ptr`
+    **  @param  ptr: *mut S          the pointer to check
+    **  @retval VMResult<()>         Ok if the pointer is user writable
+    */
+    pub fn check_write_ptr<S>(&self, ptr: *mut S) -> VMResult<()> {
+        self.check_write_array(ptr, 1)
+    }
+    /*
+    **  @brief  check a user C string at `ptr` and copy it into the kernel
+    **          the memory set must be the active one
+    **  @param  ptr: *const u8       the beginning of the '\0' terminated string
+    **  @retval VMResult<String>     the copied string without the '\0'
+    */
+    pub fn check_and_clone_cstr(&self, ptr: *const u8) -> VMResult<String> {
+        let mut bytes = Vec::new();
+        let mut addr = ptr as VirtAddr;
+        loop {
+            let area = self.areas.iter()
+                .find(|area| area.check_user_read(addr, addr + 1))
+                .ok_or(VMError::InvalidPtr)?;
+            while addr < area.end_addr {
+                let c = unsafe { *(addr as *const u8) };
+                if c == 0 {
+                    return String::from_utf8(bytes).map_err(|_| VMError::InvalidUtf8);
+                }
+                bytes.push(c);
+                addr += 1;
+            }
+        }
+    }
+    /*
+    **  @brief  check the range of `count` elements of `size` bytes at `start`
+    **          is covered by areas satisfying `check`
+    **  @retval VMResult<()>         Ok if the whole range is covered
+    */
+    fn check_range(&self, start: VirtAddr, count: usize, size: usize,
+                   check: impl Fn(&MemoryArea, VirtAddr, VirtAddr) -> bool) -> VMResult<()> {
+        let end = count.checked_mul(size)
+            .and_then(|len| start.checked_add(len))
+            .ok_or(VMError::InvalidPtr)?;
+        let mut addr = start;
+        // the range may span several adjacent areas
+        while addr < end {
+            let area = self.areas.iter()
+                .find(|area| area.contains(addr))
+                .ok_or(VMError::InvalidPtr)?;
+            let area_end = end.min(area.end_addr);
+            if !check(area, addr, area_end) {
+                return Err(VMError::InvalidPtr);
+            }
+            addr = area_end;
+        }
+        Ok(())
+    }
+    /*
     **  @brief  add the memory area to the memory set
-    **  @param  area: MemoryArea     the memory area to add
+    **  @param  start_addr: VirtAddr the beginning of the area
+    **  @param  end_addr: VirtAddr   the end of the area
+    **  @param  attr: MemoryAttr     the attribute of the area
+    **  @param  handler: impl MemoryHandler
+    **                               the handler mapping the pages of the area
+    **  @param  name: &'static str   the name of the area
     **  @retval none
     */
-    pub fn push(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, handler: impl MemoryHandler, name: &'static str) {
+    pub fn push(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, attr: MemoryAttr, handler: impl MemoryHandler, name: &'static str) {
         assert!(start_addr <= end_addr, "invalid memory area");
-        let area = MemoryArea { start_addr, end_addr, handler: Box::new(handler), name };
+        let area = MemoryArea { start_addr, end_addr, attr, handler: Box::new(handler), name };
         assert!(self.areas.iter()
                     .find(|other| area.is_overlap_with(other))
                     .is_none(), "memory area overlap");
@@ -181,6 +337,106 @@ impl<T: InactivePageTable> MemorySet<T> {
         self.areas.push(area);
     }
     /*
+    **  @brief  grow the memory area ending at `end_addr` to `new_end_addr` in place
+    **          the new pages are mapped by the handler of the area
+    **  @param  end_addr: VirtAddr   the page aligned end of the area
+    **  @param  new_end_addr: VirtAddr
+    **                               the page aligned new end of the area
+    **  @retval VMResult<()>         Err if no area ends at `end_addr`, or the new range is not free
+    */
+    pub fn grow(&mut self, end_addr: VirtAddr, new_end_addr: VirtAddr) -> VMResult<()> {
+        assert!(end_addr <= new_end_addr, "invalid memory range");
+        if end_addr == new_end_addr {
+            return Ok(());
+        }
+        if self.areas.iter().any(|area| area.is_overlap_with_range(end_addr, new_end_addr)) {
+            return Err(VMError::InvalidPtr);
+        }
+        let Self { ref mut page_table, ref mut areas } = self;
+        let area = areas.iter_mut()
+            .find(|area| area.end_addr == end_addr)
+            .ok_or(VMError::InvalidPtr)?;
+        page_table.edit(|pt| {
+            for page in Page::range_of(end_addr, new_end_addr) {
+                area.handler.map(pt, page.start_address(), &area.attr);
+            }
+        });
+        area.end_addr = new_end_addr;
+        Ok(())
+    }
+    /*
+    **  @brief  find a free range of `len` bytes which is not lower than `addr_hint`
+    **  @param  addr_hint: VirtAddr  the lowest address to start searching
+    **  @param  len: usize           the length of the range
+    **  @retval Option<VirtAddr>     the page aligned beginning of the range, if present
+    */
+    pub fn find_free_area(&self, addr_hint: VirtAddr, len: usize) -> Option<VirtAddr> {
+        let mut addr = Page::of_addr(addr_hint.checked_add(PAGE_SIZE - 1)?).start_address();
+        loop {
+            let end_addr = addr.checked_add(len)?;
+            match self.areas.iter().find(|area| area.is_overlap_with_range(addr, end_addr)) {
+                Some(area) => addr = Page::of_addr(area.end_addr.checked_add(PAGE_SIZE - 1)?).start_address(),
+                None => return Some(addr),
+            }
+        }
+    }
+    /*
+    **  @brief  remove the range [`start_addr`, `end_addr`) from the memory set
+    **          areas partly in the range are split, and only the pages in the range are unmapped
+    **  @param  start_addr: VirtAddr the page aligned beginning of the range
+    **  @param  end_addr: VirtAddr   the page aligned end of the range
+    **  @retval none
+    */
+    pub fn remove(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        self.split_at(start_addr);
+        self.split_at(end_addr);
+        let Self { ref mut page_table, ref mut areas, .. } = self;
+        page_table.edit(|pt| {
+            for area in areas.iter().filter(|area| area.is_inside(start_addr, end_addr)) {
+                area.unmap(pt);
+            }
+        });
+        areas.retain(|area| !area.is_inside(start_addr, end_addr));
+    }
+    /*
+    **  @brief  change the attribute of the range [`start_addr`, `end_addr`) in the memory set
+    **          areas partly in the range are split, and the page table entries are rewritten
+    **  @param  start_addr: VirtAddr the page aligned beginning of the range
+    **  @param  end_addr: VirtAddr   the page aligned end of the range
+    **  @param  attr: MemoryAttr     the new attribute of the range
+    **  @retval VMResult<()>         Err if the range is not fully covered by user areas
+    */
+    pub fn protect(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, attr: MemoryAttr) -> VMResult<()> {
+        assert!(start_addr <= end_addr, "invalid memory range");
+        self.check_range(start_addr, end_addr - start_addr, 1, |area, _, _| area.attr.user)?;
+        self.split_at(start_addr);
+        self.split_at(end_addr);
+        let Self { ref mut page_table, ref mut areas, .. } = self;
+        page_table.edit(|pt| {
+            for area in areas.iter_mut().filter(|area| area.is_inside(start_addr, end_addr)) {
+                area.attr = attr;
+                area.protect(pt);
+            }
+        });
+        Ok(())
+    }
+    /*
+    **  @brief  split the memory area containing `addr` into two areas at `addr`
+    **          nothing is done if `addr` is not strictly inside an area
+    **  @param  addr: VirtAddr       the page aligned address to split at
+    **  @retval none
+    */
+    fn split_at(&mut self, addr: VirtAddr) {
+        let pos = self.areas.iter()
+            .position(|area| area.start_addr < addr && addr < area.end_addr);
+        if let Some(pos) = pos {
+            let mut right = self.areas[pos].clone();
+            right.start_addr = addr;
+            self.areas[pos].end_addr = addr;
+            self.areas.insert(pos + 1, right);
+        }
+    }
+    /*
     **  @brief  get iterator of the memory area
     **  @retval impl Iterator<Item=&MemoryArea>
     **                               the memory area iterator
@@ -238,10 +494,64 @@ impl<T: InactivePageTable> MemorySet<T> {
     pub fn page_fault_handler(&mut self, addr: VirtAddr) -> bool {
         let area = self.areas.iter().find(|area| area.contains(addr));
         match area {
+            // the pages of a hidden area are not allocated, so they stay inaccessible
+            Some(area) if area.attr.hide => false,
             Some(area) => self.page_table.edit(|pt| area.handler.page_fault_handler(pt, addr)),
             None => false,
         }
     }
+    /*
+    **  @brief  handle the page fault at `addr` with the data loaded by the handler
+    **          (see `MemoryHandler::load_page`, which is called without the memory set locked)
+    **  @param  addr: VirtAddr       the virtual address of the page fault
+    **  @param  data: &[u8]          the data of the page
+    **  @retval bool                 whether the page fault is handled
+    */
+    pub fn page_fault_handler_with(&mut self, addr: VirtAddr, data: &[u8]) -> bool {
+        let area = self.areas.iter().find(|area| area.contains(addr));
+        match area {
+            Some(area) if area.attr.hide => false,
+            Some(area) => self.page_table.edit(|pt| area.handler.page_fault_handler_with(pt, addr, data)),
+            None => false,
+        }
+    }
+}
+
+impl<T: InactivePageTable<Active=CowExt<A>>, A: PageTable> MemorySet<T> {
+    /*
+    **  @brief  fork the memory set with copy-on-write
+    **          the present pages are shared by the two memory sets, and copied on write,
+    **          other pages are mapped by the handler in the new memory set
+    **  @retval MemorySet<T>         the forked memory set
+    */
+    pub fn fork(&mut self) -> Self {
+        let Self { ref mut page_table, ref areas } = self;
+        // only one page table can be edited at a time,
+        // so share the pages in this one first, then map them in the new one
+        let targets: Vec<Option<PhysAddr>> = page_table.edit(|pt| {
+            areas.iter()
+                .flat_map(|area| Page::range_of(area.start_addr, area.end_addr))
+                .map(|page| pt.share(page.start_address()))
+                .collect()
+        });
+        let mut page_table = T::new();
+        page_table.edit(|pt| {
+            let mut targets = targets.into_iter();
+            for area in areas.iter() {
+                for page in Page::range_of(area.start_addr, area.end_addr) {
+                    let addr = page.start_address();
+                    match targets.next().unwrap() {
+                        Some(target) => area.attr.apply(pt.map_to_shared(addr, target, !area.attr.readonly)),
+                        None => area.handler.map(pt, addr, &area.attr),
+                    }
+                }
+            }
+        });
+        MemorySet {
+            areas: areas.clone(),
+            page_table,
+        }
+    }
 }
 
 impl<T: InactivePageTable> Clone for MemorySet<T> {
@@ -265,6 +575,17 @@ impl<T: InactivePageTable> Drop for MemorySet<T> {
     }
 }
 
+/// Error when checking the user memory
+#[derive(Debug, Eq, PartialEq)]
+pub enum VMError {
+    /// the memory is not fully covered by areas with the required attribute
+    InvalidPtr,
+    /// the C string is not valid UTF-8
+    InvalidUtf8,
+}
+
+pub type VMResult<T> = Result<T, VMError>;
+
 impl<T: InactivePageTable> Debug for MemorySet<T> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         f.debug_list()
@@ -272,3 +593,312 @@ impl<T: InactivePageTable> Debug for MemorySet<T> {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::handler::{ByFrame, Delay, File, FrameAllocator, Read};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::cell::RefCell;
+
+    thread_local! {
+        /// The page table being edited, shared by all the inactive ones like the recursive mapping
+        static ACTIVE: RefCell<CowExt<MockPageTable>> = RefCell::new(CowExt::new(MockPageTable::new()));
+    }
+
+    struct MockInactivePageTable(Box<MockPageTable>);
+
+    impl InactivePageTable for MockInactivePageTable {
+        type Active = CowExt<MockPageTable>;
+
+        fn new_bare() -> Self {
+            MockInactivePageTable(Box::new(MockPageTable::new()))
+        }
+        fn map_kernel(&mut self) {}
+        fn token(&self) -> usize {
+            &*self.0 as *const MockPageTable as usize
+        }
+        unsafe fn set_token(_token: usize) {}
+        fn active_token() -> usize {
+            0
+        }
+        fn flush_tlb() {}
+        fn edit<T>(&mut self, f: impl FnOnce(&mut Self::Active) -> T) -> T {
+            ACTIVE.with(|active| {
+                let mut active = active.borrow_mut();
+                core::mem::swap(&mut **active, &mut *self.0);
+                let ret = f(&mut *active);
+                core::mem::swap(&mut **active, &mut *self.0);
+                ret
+            })
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct MockFrameAlloc;
+
+    impl FrameAllocator for MockFrameAlloc {
+        fn alloc(&self) -> Option<PhysAddr> {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            Some(NEXT.fetch_add(1, Ordering::Relaxed) % 16 * PAGE_SIZE)
+        }
+        fn dealloc(&self, _target: PhysAddr) {}
+    }
+
+    /// A file whose byte at `offset` is `offset as u8`, and whose second page can't be read
+    #[derive(Clone)]
+    struct MockFile;
+
+    impl Read for MockFile {
+        fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, ()> {
+            if offset + buf.len() > PAGE_SIZE {
+                return Err(());
+            }
+            buf.iter_mut().enumerate().for_each(|(i, x)| *x = (offset + i) as u8);
+            Ok(buf.len())
+        }
+    }
+
+    type MockMemorySet = MemorySet<MockInactivePageTable>;
+
+    fn ranges(ms: &MockMemorySet) -> Vec<(VirtAddr, VirtAddr)> {
+        ms.iter().map(|area| (area.start_addr, area.end_addr)).collect()
+    }
+
+    fn present(ms: &mut MockMemorySet, addr: VirtAddr) -> bool {
+        ms.page_table.edit(|pt| pt.get_entry(addr).unwrap().present())
+    }
+
+    #[test]
+    fn check_range() {
+        let mut ms = MockMemorySet::new();
+        let user = MemoryAttr::default().user();
+        ms.push(0x1000, 0x3000, user, ByFrame::new(MockFrameAlloc), "rw");
+        ms.push(0x3000, 0x4000, user.readonly(), ByFrame::new(MockFrameAlloc), "ro");
+        ms.push(0x5000, 0x6000, MemoryAttr::default(), ByFrame::new(MockFrameAlloc), "kernel");
+
+        // across adjacent areas
+        assert_eq!(ms.check_read_array(0x1800 as *const u8, 0x2000), Ok(()));
+        assert_eq!(ms.check_write_array(0x1800 as *mut u32, 0x400), Ok(()));
+        assert_eq!(ms.check_write_array(0x2800 as *mut u8, 0x1000), Err(VMError::InvalidPtr));
+        assert_eq!(ms.check_write_ptr(0x3000 as *mut u8), Err(VMError::InvalidPtr));
+        // the end is exclusive
+        assert_eq!(ms.check_read_array(0x3800 as *const u8, 0x800), Ok(()));
+        assert_eq!(ms.check_read_array(0x3800 as *const u8, 0x801), Err(VMError::InvalidPtr));
+        // not covered, or not for the user
+        assert_eq!(ms.check_read_ptr(0x4000 as *const u8), Err(VMError::InvalidPtr));
+        assert_eq!(ms.check_read_ptr(0x5000 as *const u8), Err(VMError::InvalidPtr));
+        assert_eq!(ms.check_read_ptr(0x2ffe as *const u32), Ok(()));
+        assert_eq!(ms.check_read_ptr(0x3ffe as *const u32), Err(VMError::InvalidPtr));
+        // overflow
+        assert_eq!(ms.check_read_array(0x1000 as *const u64, usize::max_value() / 4), Err(VMError::InvalidPtr));
+        assert_eq!(ms.check_read_array(usize::max_value() as *const u8, 2), Err(VMError::InvalidPtr));
+        // empty
+        assert_eq!(ms.check_read_array(0x1000 as *const u8, 0), Ok(()));
+    }
+
+    #[test]
+    fn find_free_area() {
+        let mut ms = MockMemorySet::new();
+        let user = MemoryAttr::default().user();
+        ms.push(0x2000, 0x4000, user, ByFrame::new(MockFrameAlloc), "");
+        ms.push(0x5000, 0x6000, user, ByFrame::new(MockFrameAlloc), "");
+
+        assert_eq!(ms.find_free_area(0x1000, 0x1000), Some(0x1000));
+        // the hint is rounded up to a page
+        assert_eq!(ms.find_free_area(0x800, 0x1000), Some(0x1000));
+        assert_eq!(ms.find_free_area(0x1000, 0x2000), Some(0x6000));
+        assert_eq!(ms.find_free_area(0x2000, 0x1000), Some(0x4000));
+        assert_eq!(ms.find_free_area(0x3000, 0x1001), Some(0x6000));
+        assert_eq!(ms.find_free_area(usize::max_value() - 0x1000, 0x1000), None);
+    }
+
+    #[test]
+    fn remove() {
+        let mut ms = MockMemorySet::new();
+        let user = MemoryAttr::default().user();
+        ms.push(0x1000, 0x5000, user, ByFrame::new(MockFrameAlloc), "a");
+        ms.push(0x6000, 0x8000, user, ByFrame::new(MockFrameAlloc), "b");
+        ms.push(0x8000, 0x9000, user, ByFrame::new(MockFrameAlloc), "c");
+
+        // split an area in the middle
+        ms.remove(0x2000, 0x3000);
+        assert_eq!(ranges(&ms), [(0x1000, 0x2000), (0x3000, 0x5000), (0x6000, 0x8000), (0x8000, 0x9000)]);
+        assert!(present(&mut ms, 0x1000));
+        assert!(!present(&mut ms, 0x2000));
+        assert!(present(&mut ms, 0x3000));
+
+        // shrink the areas at both ends, and remove those inside, across a gap
+        ms.remove(0x4000, 0x7000);
+        assert_eq!(ranges(&ms), [(0x1000, 0x2000), (0x3000, 0x4000), (0x7000, 0x8000), (0x8000, 0x9000)]);
+        assert!(present(&mut ms, 0x3000));
+        assert!(!present(&mut ms, 0x4000));
+        assert!(!present(&mut ms, 0x6000));
+        assert!(present(&mut ms, 0x7000));
+
+        // remove whole areas exactly
+        ms.remove(0x7000, 0x9000);
+        assert_eq!(ranges(&ms), [(0x1000, 0x2000), (0x3000, 0x4000)]);
+        assert!(!present(&mut ms, 0x8000));
+
+        // nothing in the range
+        ms.remove(0x4000, 0x6000);
+        assert_eq!(ranges(&ms), [(0x1000, 0x2000), (0x3000, 0x4000)]);
+
+        // the removed range can be used again
+        ms.push(0x2000, 0x3000, user, ByFrame::new(MockFrameAlloc), "d");
+        assert!(present(&mut ms, 0x2000));
+    }
+
+    #[test]
+    fn split_at() {
+        let mut ms = MockMemorySet::new();
+        let user = MemoryAttr::default().user();
+        ms.push(0x1000, 0x4000, user, ByFrame::new(MockFrameAlloc), "a");
+
+        // at the boundaries or out of the areas
+        ms.split_at(0x1000);
+        ms.split_at(0x4000);
+        ms.split_at(0x5000);
+        assert_eq!(ranges(&ms), [(0x1000, 0x4000)]);
+
+        ms.split_at(0x3000);
+        ms.split_at(0x2000);
+        assert_eq!(ranges(&ms), [(0x1000, 0x2000), (0x2000, 0x3000), (0x3000, 0x4000)]);
+        assert!(ms.iter().all(|area| area.attr == user && area.name == "a"));
+    }
+
+    #[test]
+    fn grow() {
+        let mut ms = MockMemorySet::new();
+        let user = MemoryAttr::default().user();
+        ms.push(0x1000, 0x2000, user, ByFrame::new(MockFrameAlloc), "a");
+        ms.push(0x5000, 0x6000, user, ByFrame::new(MockFrameAlloc), "b");
+
+        // the new pages are mapped by the handler of the area
+        assert_eq!(ms.grow(0x2000, 0x4000), Ok(()));
+        assert_eq!(ranges(&ms), [(0x1000, 0x4000), (0x5000, 0x6000)]);
+        assert!(present(&mut ms, 0x3000));
+
+        // no area ends there, or it would overlap another area
+        assert_eq!(ms.grow(0x3000, 0x4000), Err(VMError::InvalidPtr));
+        assert_eq!(ms.grow(0x4000, 0x6000), Err(VMError::InvalidPtr));
+        assert_eq!(ranges(&ms), [(0x1000, 0x4000), (0x5000, 0x6000)]);
+        assert!(!present(&mut ms, 0x4000));
+
+        // up to the next area
+        assert_eq!(ms.grow(0x4000, 0x5000), Ok(()));
+        assert_eq!(ranges(&ms), [(0x1000, 0x5000), (0x5000, 0x6000)]);
+    }
+
+    #[test]
+    fn protect() {
+        let mut ms = MockMemorySet::new();
+        let user = MemoryAttr::default().user();
+        ms.push(0x1000, 0x4000, user, ByFrame::new(MockFrameAlloc), "a");
+        ms.push(0x4000, 0x6000, user, Delay::new(MockFrameAlloc), "b");
+        ms.push(0x7000, 0x8000, MemoryAttr::default(), ByFrame::new(MockFrameAlloc), "kernel");
+
+        assert_eq!(ms.protect(0x2000, 0x5000, user.readonly()), Ok(()));
+        assert_eq!(ranges(&ms), [(0x1000, 0x2000), (0x2000, 0x4000), (0x4000, 0x5000), (0x5000, 0x6000), (0x7000, 0x8000)]);
+        assert_eq!(ms.check_write_ptr(0x1000 as *mut u8), Ok(()));
+        assert_eq!(ms.check_write_ptr(0x2000 as *mut u8), Err(VMError::InvalidPtr));
+        assert_eq!(ms.check_read_array(0x2000 as *const u8, 0x3000), Ok(()));
+        ms.edit(|pt| {
+            assert!(pt.get_entry(0x1000).unwrap().writable());
+            assert!(!pt.get_entry(0x3000).unwrap().writable());
+            // a page not allocated yet stays not present
+            assert!(!pt.get_entry(0x4000).unwrap().present());
+        });
+        assert!(ms.page_fault_handler(0x4000));
+        ms.edit(|pt| {
+            let entry = pt.get_entry(0x4000).unwrap();
+            assert!(entry.present() && !entry.writable());
+        });
+
+        // hidden from the user
+        assert_eq!(ms.protect(0x5000, 0x6000, user.hide()), Ok(()));
+        assert_eq!(ms.check_read_ptr(0x5000 as *const u8), Err(VMError::InvalidPtr));
+        assert!(!ms.page_fault_handler(0x5000));
+        assert_eq!(ms.protect(0x1000, 0x2000, user.hide()), Ok(()));
+        ms.edit(|pt| {
+            let entry = pt.get_entry(0x1000).unwrap();
+            assert!(entry.present() && !entry.user());
+        });
+        // and shown again
+        assert_eq!(ms.protect(0x1000, 0x2000, user), Ok(()));
+        assert_eq!(ms.check_write_ptr(0x1000 as *mut u8), Ok(()));
+        ms.edit(|pt| assert!(pt.get_entry(0x1000).unwrap().user()));
+
+        // not fully covered by user areas, nothing is changed
+        assert_eq!(ms.protect(0x5000, 0x7000, user.readonly()), Err(VMError::InvalidPtr));
+        assert_eq!(ms.protect(0x7000, 0x8000, user), Err(VMError::InvalidPtr));
+        assert_eq!(ranges(&ms), [(0x1000, 0x2000), (0x2000, 0x4000), (0x4000, 0x5000), (0x5000, 0x6000), (0x7000, 0x8000)]);
+        assert_eq!(ms.check_read_ptr(0x5000 as *const u8), Err(VMError::InvalidPtr));
+    }
+
+    #[test]
+    fn file() {
+        let mut ms = MockMemorySet::new();
+        let user = MemoryAttr::default().user();
+        ms.push(0x1000, 0x4000, user.readonly(), File::new(MockFile, 0x1000, 0, 0x1800, MockFrameAlloc), "file");
+
+        // loaded without the memory set, then mapped readonly
+        let data = ms.find_area(0x1000).unwrap().get_handler().load_page(0x1234).unwrap().unwrap();
+        assert!(ms.page_fault_handler_with(0x1234, &data));
+        ms.edit(|pt| {
+            assert!(!pt.get_entry(0x1000).unwrap().writable());
+            assert_eq!(pt.read(0x1012), 0x12);
+        });
+
+        // a page which fails to be read is not mapped
+        assert_eq!(ms.find_area(0x2000).unwrap().get_handler().load_page(0x2000), Err(()));
+        assert!(!ms.page_fault_handler(0x2000));
+        assert!(!present(&mut ms, 0x2000));
+
+        // beyond the file, filled with zeros
+        assert!(ms.page_fault_handler(0x3000));
+        ms.edit(|pt| assert_eq!(pt.read(0x3000), 0));
+    }
+
+    #[test]
+    fn fork() {
+        let mut ms = MockMemorySet::new();
+        let user = MemoryAttr::default().user();
+        ms.push(0x1000, 0x2000, user, ByFrame::new(MockFrameAlloc), "rw");
+        ms.push(0x2000, 0x3000, user.readonly(), ByFrame::new(MockFrameAlloc), "ro");
+        ms.push(0x3000, 0x4000, user, Delay::new(MockFrameAlloc), "delay");
+        let target = ms.page_table.edit(|pt| pt.get_entry(0x1000).unwrap().target());
+
+        let mut child = ms.fork();
+        assert_eq!(ranges(&child), ranges(&ms));
+        for ms in [&mut ms, &mut child].iter_mut() {
+            ms.page_table.edit(|pt| {
+                let entry = pt.get_entry(0x1000).unwrap();
+                assert!(entry.writable_shared() && !entry.writable());
+                assert_eq!(entry.target(), target);
+                let entry = pt.get_entry(0x2000).unwrap();
+                assert!(entry.readonly_shared() && !entry.writable());
+                // not allocated yet, so not shared
+                assert!(!pt.get_entry(0x3000).unwrap().present());
+            });
+        }
+
+        // the first writer gets a copy, and the last one gets the frame back
+        let frame = MockFrameAlloc.alloc().unwrap();
+        assert!(ms.page_table.edit(|pt| pt.page_fault_handler(0x1000, || frame)));
+        assert!(child.page_table.edit(|pt| pt.page_fault_handler(0x1000, || unreachable!())));
+        ms.page_table.edit(|pt| {
+            let entry = pt.get_entry(0x1000).unwrap();
+            assert!(entry.writable() && !entry.writable_shared());
+            assert_eq!(entry.target(), frame);
+        });
+        child.page_table.edit(|pt| {
+            let entry = pt.get_entry(0x1000).unwrap();
+            assert!(entry.writable() && !entry.writable_shared());
+            assert_eq!(entry.target(), target);
+        });
+        // a readonly page is not copied
+        assert!(!child.page_table.edit(|pt| pt.page_fault_handler(0x2000, || unreachable!())));
+    }
+}
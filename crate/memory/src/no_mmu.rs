@@ -1,6 +1,7 @@
 use alloc::vec::Vec;
 use alloc::alloc::{Layout, GlobalAlloc};
 use core::marker::PhantomData;
+use crate::memory_set::VMResult;
 
 pub trait NoMMUSupport {
     type Alloc: GlobalAlloc;
@@ -27,6 +28,11 @@ impl<S: NoMMUSupport> MemorySet<S> {
         self.areas.push(area);
         slice
     }
+    // without MMU every address is accessible
+    pub fn check_read_array<T>(&self, _ptr: *const T, _count: usize) -> VMResult<()> { Ok(()) }
+    pub fn check_write_array<T>(&self, _ptr: *mut T, _count: usize) -> VMResult<()> { Ok(()) }
+    pub fn check_read_ptr<T>(&self, _ptr: *const T) -> VMResult<()> { Ok(()) }
+    pub fn check_write_ptr<T>(&self, _ptr: *mut T) -> VMResult<()> { Ok(()) }
     // empty impls
     pub fn with<T>(&self, f: impl FnOnce() -> T) -> T { f() }
     pub fn token(&self) -> usize { 0 }
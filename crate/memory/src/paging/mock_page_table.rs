@@ -27,6 +27,9 @@ pub struct MockEntry {
     writable_shared: bool,
     readonly_shared: bool,
     swapped: bool,
+    user: bool,
+    execute: bool,
+    mmio: u8,
 }
 
 impl Entry for MockEntry {
@@ -53,12 +56,12 @@ impl Entry for MockEntry {
     }
     fn swapped(&self) -> bool { self.swapped }
     fn set_swapped(&mut self, value: bool) { self.swapped = value; }
-    fn user(&self) -> bool { unimplemented!() }
-    fn set_user(&mut self, value: bool) { unimplemented!() }
-    fn execute(&self) -> bool { unimplemented!() }
-    fn set_execute(&mut self, value: bool) { unimplemented!() }
-    fn mmio(&self) -> bool { unimplemented!() }
-    fn set_mmio(&mut self, value: bool) { unimplemented!() }
+    fn user(&self) -> bool { self.user }
+    fn set_user(&mut self, value: bool) { self.user = value; }
+    fn execute(&self) -> bool { self.execute }
+    fn set_execute(&mut self, value: bool) { self.execute = value; }
+    fn mmio(&self) -> u8 { self.mmio }
+    fn set_mmio(&mut self, value: u8) { self.mmio = value; }
 }
 
 type PageFaultHandler = Box<FnMut(&mut MockPageTable, VirtAddr)>;
@@ -26,6 +26,11 @@ pub trait PageTable {
     /// If its page do not exist, return `None`
     fn get_entry(&mut self, addr: VirtAddr) -> Option<&mut Entry>;
 
+    /// Give the frame of a shared page of virual address `addr` back to the page,
+    /// if no other page table uses it any more
+    /// Called before the frame is deallocated on unmap
+    fn unshare(&mut self, _addr: VirtAddr) {}
+
     /// Get a mutable reference of the content of a page of virtual address `addr`
     /// Used for testing with mock
     fn get_page_slice_mut<'a>(&mut self, addr: VirtAddr) -> &'a mut [u8] {
@@ -0,0 +1,76 @@
+//! Implement the swap manager with the aging page replacement algorithm
+//!
+//! Each page has an 8-bit age. At each tick, the ages are shifted right,
+//! and the accessed bit of each page is shifted in as the highest bit, then cleared.
+//! The page with the least age, which is about the least recently used one, is swapped out.
+
+use alloc::collections::VecDeque;
+use super::*;
+
+#[derive(Default)]
+pub struct AgingSwapManager {
+    /// the frames with their ages, in the order pushed
+    deque: VecDeque<(Frame, u8)>,
+}
+
+impl SwapManager for AgingSwapManager {
+    fn tick<T: PageTable>(&mut self, page_table: &mut T) {
+        for (frame, age) in self.deque.iter_mut() {
+            *age >>= 1;
+            if let Some(entry) = page_table.get_entry(frame.get_virtaddr()) {
+                if entry.accessed() {
+                    *age |= 0x80;
+                    entry.clear_accessed();
+                    entry.update();
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, frame: Frame) {
+        // it is just accessed when brought into memory
+        self.deque.push_back((frame, 0x80));
+    }
+
+    fn remove(&mut self, token: usize, addr: VirtAddr) {
+        let id = self.deque.iter()
+            .position(|(x, _)| x.get_virtaddr() == addr && x.get_token() == token)
+            .expect("address not found");
+        self.deque.remove(id);
+    }
+
+    fn pop<T, S>(&mut self, _: &mut T, _: &mut S) -> Option<Frame>
+        where T: PageTable, S: Swapper
+    {
+        // the first one pushed among the least aged
+        let id = self.deque.iter()
+            .enumerate()
+            .min_by_key(|(_, (_, age))| *age)
+            .map(|(id, _)| id)?;
+        self.deque.remove(id).map(|(frame, _)| frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::swap::test::*;
+
+    #[test]
+    fn test() {
+        use self::MemOp::{R, Tick};
+        let ops = [
+            R(0x1000), R(0x2000), R(0x3000), R(0x4000), Tick,
+            R(0x1000), R(0x2000), Tick,
+            R(0x1000), R(0x3000), Tick,
+            R(0x5000), R(0x4000), Tick,
+            R(0x2000), R(0x1000)];
+        let pgfault_count = [
+            1, 2, 3, 4, 4,
+            4, 4, 4,
+            4, 4, 4,
+            5, 6, 6,
+            7, 7];
+        test_manager(AgingSwapManager::default(), &ops, &pgfault_count);
+    }
+}
@@ -0,0 +1,93 @@
+//! A circular list with a hand, shared by the clock page replacement algorithms
+
+use alloc::collections::VecDeque;
+use core::ops::{Index, IndexMut};
+
+pub struct Clock<T> {
+    /// the items in the order the hand passes
+    deque: VecDeque<T>,
+    /// the index of the item the hand points to
+    ptr: usize,
+}
+
+impl<T> Default for Clock<T> {
+    fn default() -> Self {
+        Clock {
+            deque: VecDeque::new(),
+            ptr: 0,
+        }
+    }
+}
+
+impl<T> Clock<T> {
+    pub fn len(&self) -> usize {
+        self.deque.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.deque.is_empty()
+    }
+    /*
+    **  @brief  get the index of the item the hand points to
+    **  @retval usize                the index of the item
+    */
+    pub fn hand(&self) -> usize {
+        self.ptr
+    }
+    /*
+    **  @brief  find the index of the first item satisfying `f`
+    **  @param  f: impl FnMut(&T) -> bool
+    **                               the predicate
+    **  @retval Option<usize>        the index of the item, if present
+    */
+    pub fn position(&self, f: impl FnMut(&T) -> bool) -> Option<usize> {
+        self.deque.iter().position(f)
+    }
+    /*
+    **  @brief  insert an item just behind the hand, so it is the last one to check
+    **  @param  item: T              the item to insert
+    **  @retval none
+    */
+    pub fn push(&mut self, item: T) {
+        self.deque.insert(self.ptr, item);
+        self.move_next();
+    }
+    /*
+    **  @brief  remove the item at the index, keeping the hand pointing to the same item or the next one
+    **  @param  id: usize            the index of the item
+    **  @retval T                    the item removed
+    */
+    pub fn remove_at(&mut self, id: usize) -> T {
+        let item = self.deque.remove(id).unwrap();
+        if id < self.ptr {
+            self.ptr -= 1;
+        }
+        if self.ptr == self.deque.len() {
+            self.ptr = 0;
+        }
+        item
+    }
+    /*
+    **  @brief  move the hand to the next item
+    **  @retval none
+    */
+    pub fn move_next(&mut self) {
+        self.ptr += 1;
+        if self.ptr == self.deque.len() {
+            self.ptr = 0;
+        }
+    }
+}
+
+impl<T> Index<usize> for Clock<T> {
+    type Output = T;
+
+    fn index(&self, id: usize) -> &T {
+        &self.deque[id]
+    }
+}
+
+impl<T> IndexMut<usize> for Clock<T> {
+    fn index_mut(&mut self, id: usize) -> &mut T {
+        &mut self.deque[id]
+    }
+}
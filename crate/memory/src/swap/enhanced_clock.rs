@@ -1,80 +1,60 @@
-//! Implememnt the swap manager with the enhanced clock page replacement algorithm
+//! Implement the swap manager with the enhanced clock page replacement algorithm
+//!
+//! The pages are classified by their accessed and dirty bits.
+//! The clock hand looks for a page neither accessed nor dirty in the first round,
+//! then for a page not accessed but dirty in the second round, clearing the accessed bits it passes.
+//! The two rounds are repeated once more at most, when all the accessed bits are cleared.
 
-use alloc::collections::VecDeque;
 use super::*;
-use crate::paging::Entry;
+use super::clock::Clock;
 
 #[derive(Default)]
 pub struct EnhancedClockSwapManager {
-    clock_ptr: usize,
-    deque: VecDeque<VirtAddr>,
+    clock: Clock<Frame>,
 }
 
-// FIXME: It's unusable. But can pass a simple test.
 impl SwapManager for EnhancedClockSwapManager {
-    fn tick(&mut self) {
-    }
+    fn tick<T: PageTable>(&mut self, _: &mut T) {}
 
-    fn push(&mut self, addr: usize) {
-        let pos = if self.clock_ptr == 0 {self.deque.len()} else {self.clock_ptr};
-        self.deque.insert(pos, addr);
+    fn push(&mut self, frame: Frame) {
+        self.clock.push(frame);
     }
 
-    fn remove(&mut self, addr: usize) {
-        let id = self.deque.iter()
-            .position(|&x| x == addr)
+    fn remove(&mut self, token: usize, addr: VirtAddr) {
+        let id = self.clock
+            .position(|x| x.get_virtaddr() == addr && x.get_token() == token)
             .expect("address not found");
-        if id < self.clock_ptr {
-            self.clock_ptr -= 1;
-        }
-        self.deque.remove(id);
+        self.clock.remove_at(id);
     }
 
-    fn pop<T, S>(&mut self, page_table: &mut T, _swapper: &mut S) -> Option<VirtAddr>
+    fn pop<T, S>(&mut self, page_table: &mut T, _: &mut S) -> Option<Frame>
         where T: PageTable, S: Swapper
     {
+        let clock = &mut self.clock;
+        if clock.is_empty() {
+            return None;
+        }
         loop {
-            let addr = self.deque[self.clock_ptr];
-            // FIXME: Once define `slice`, all modifies of `entry` below will fail.
-            //        Then the loop will never stop.
-            //        The reason may be `get_page_slice_mut()` contains unsafe operation,
-            //        which lead the compiler to do a wrong optimization.
-//            let slice = page_table.get_page_slice_mut(addr);
-            let entry = page_table.get_entry(addr).unwrap();
-//            println!("{:#x} , {}, {}", addr, entry.accessed(), entry.dirty());
-
-            match (entry.accessed(), entry.dirty()) {
-                (true, _) => {
-                    entry.clear_accessed();
-                },
-                (false, true) => {
-                    // FIXME:
-//                    if let Ok(token) = swapper.swap_out(slice) {
-//                        entry.clear_dirty();
-//                    }
-                },
-                _ => {
-                    return self.remove_current();
+            // neither accessed nor dirty
+            for _ in 0..clock.len() {
+                let addr = clock[clock.hand()].get_virtaddr();
+                match page_table.get_entry(addr) {
+                    Some(entry) if entry.accessed() || entry.dirty() => clock.move_next(),
+                    _ => return Some(clock.remove_at(clock.hand())),
                 }
             }
-            self.move_next();
-        }
-    }
-}
-
-
-impl EnhancedClockSwapManager {
-    fn remove_current(&mut self) -> Option<VirtAddr> {
-        let addr = self.deque.remove(self.clock_ptr);
-        if self.clock_ptr == self.deque.len() {
-            self.clock_ptr = 0;
-        }
-        return addr;
-    }
-    fn move_next(&mut self) {
-        self.clock_ptr += 1;
-        if self.clock_ptr == self.deque.len() {
-            self.clock_ptr = 0;
+            // not accessed but dirty
+            for _ in 0..clock.len() {
+                let addr = clock[clock.hand()].get_virtaddr();
+                match page_table.get_entry(addr) {
+                    Some(entry) if entry.accessed() => {
+                        entry.clear_accessed();
+                        entry.update();
+                    }
+                    _ => return Some(clock.remove_at(clock.hand())),
+                }
+                clock.move_next();
+            }
         }
     }
 }
@@ -82,7 +62,7 @@ impl EnhancedClockSwapManager {
 #[cfg(test)]
 mod test {
     use super::*;
-    use swap::test::*;
+    use crate::swap::test::*;
 
     #[test]
     fn test() {
@@ -97,4 +77,17 @@ mod test {
             5, 5, 5, 6, 7];
         test_manager(EnhancedClockSwapManager::default(), &ops, &pgfault_count);
     }
+
+    #[test]
+    fn clean_first() {
+        use self::MemOp::{R, W};
+        // 0x1000 and 0x2000 are dirty, so the clean 0x3000 and 0x4000 are swapped out first
+        let ops = [
+            W(0x1000), W(0x2000), R(0x3000), R(0x4000),
+            R(0x5000), R(0x6000), W(0x1000), W(0x2000), R(0x3000)];
+        let pgfault_count = [
+            1, 2, 3, 4,
+            5, 6, 6, 6, 7];
+        test_manager(EnhancedClockSwapManager::default(), &ops, &pgfault_count);
+    }
 }
@@ -1,4 +1,4 @@
-//! Implememnt the swap manager with the FIFO page replacement algorithm
+//! Implement the swap manager with the FIFO page replacement algorithm
 
 use alloc::collections::VecDeque;
 use super::*;
@@ -10,7 +10,7 @@ pub struct FifoSwapManager  {
 }
 
 impl SwapManager for FifoSwapManager {
-    fn tick(&mut self) {}
+    fn tick<T: PageTable>(&mut self, _: &mut T) {}
 
     fn push(&mut self, frame: Frame) {
         info!("SwapManager push token: {:x?} vaddr: {:x?}", frame.get_token(), frame.get_virtaddr());
@@ -34,11 +34,10 @@ impl SwapManager for FifoSwapManager {
     }
 }
 
-/*
 #[cfg(test)]
 mod test {
     use super::*;
-    use swap::test::*;
+    use crate::swap::test::*;
 
     #[test]
     fn test() {
@@ -56,4 +55,3 @@ mod test {
         test_manager(FifoSwapManager::default(), &ops, &pgfault_count);
     }
 }
-*/
\ No newline at end of file
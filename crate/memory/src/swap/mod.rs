@@ -10,13 +10,17 @@ use super::*;
 use super::paging::*;
 use super::addr::Frame;
 use core::ops::{Deref, DerefMut};
+use alloc::vec::Vec;
 
 //pub use self::fifo::FifoSwapManager;
 //pub use self::enhanced_clock::EnhancedClockSwapManager;
 
 pub mod fifo;
-//mod enhanced_clock;
+pub mod enhanced_clock;
+pub mod aging;
+pub mod wsclock;
 pub mod mock_swapper;
+mod clock;
 //#[cfg(test)]
 //mod mock_swapper;
 
@@ -26,9 +30,10 @@ pub trait SwapManager {
     /*
     **  @brief  update intarnal state pre tick
     **          Called when tick interrupt occured
+    **  @param  page_table: &mut T   the page table of the swappable pages
     **  @retval none
     */
-    fn tick(&mut self);
+    fn tick<T: PageTable>(&mut self, page_table: &mut T);
     /*
     **  @brief  update intarnal state when page is pushed into memory
     **          Called when map a swappable page into the memory
@@ -46,8 +51,8 @@ pub trait SwapManager {
     fn remove(&mut self, token: usize, addr: VirtAddr);
     /*
     **  @brief  select swap out victim when there is need to swap out a page
-    **          (The params is only used by `EnhancedClockSwapManager` currently)
-    **  @param  page_table: &mut T   the current page table
+    **          (The params are only used by the managers checking the entries of the pages)
+    **  @param  page_table: &mut T   the page table of the swappable pages
     **  @param  swapper: &mut S      the swapper used
     **  @retval Option<Frame>     the Frame of the victim page, if present
     */
@@ -102,6 +107,32 @@ impl<T: PageTable, M: SwapManager, S: Swapper> SwapExt<T, M, S> {
         }
     }
 
+    /*
+    **  @brief  get the mutable reference of the swap manager
+    **  @retval &mut M               the swap manager used
+    */
+    pub fn swap_manager_mut(&mut self) -> &mut M {
+        &mut self.swap_manager
+    }
+
+    /*
+    **  @brief  get the mutable reference of the swapper
+    **  @retval &mut S               the swapper used
+    */
+    pub fn swapper_mut(&mut self) -> &mut S {
+        &mut self.swapper
+    }
+
+    /*
+    **  @brief  update the state of the swap manager
+    **          Called when tick interrupt occured
+    **  @retval none
+    */
+    pub fn tick(&mut self) {
+        let Self {ref mut page_table, ref mut swap_manager, ..} = self;
+        swap_manager.tick(page_table);
+    }
+
     /*
     **  @brief set a page swappable
     **  @param pt: *mut T2           the raw pointer for the target page's inactive page table
@@ -189,16 +220,52 @@ impl<T: PageTable, M: SwapManager, S: Swapper> SwapExt<T, M, S> {
     **                               the error if failed
     */
     pub fn swap_out_any<T2: InactivePageTable>(&mut self) -> Result<PhysAddr, SwapError> {
-        info!("COME in to swap_out_any");
-        let victim: Option<Frame> = {
-            let Self {ref mut page_table, ref mut swap_manager, ref mut swapper} = self;
-            swap_manager.pop(page_table, swapper)
+        self.swap_out_any_with::<T2>(|_, swap_out| {
+            swap_out();
+            true
+        })
+    }
+
+    /*
+    **  @brief  Swap out any one of the swapped pages whose page table is not busy
+    **  @param  with_locked: impl FnMut(&Frame, &mut FnMut()) -> bool
+    **                               call the function to swap out the victim frame with its page table locked,
+    **                               or return false to skip the victim if its page table is busy
+    **  @retval Result<PhysAddr, SwapError>
+    **                               the physics address of released frame if success,
+    **                               the error if failed
+    */
+    pub fn swap_out_any_with<T2: InactivePageTable>(&mut self, mut with_locked: impl FnMut(&Frame, &mut FnMut()) -> bool) -> Result<PhysAddr, SwapError> {
+        let mut skipped = Vec::new();
+        let ret = loop {
+            let victim: Option<Frame> = {
+                let Self {ref mut page_table, ref mut swap_manager, ref mut swapper} = self;
+                swap_manager.pop(page_table, swapper)
+            };
+            let frame = match victim {
+                Some(frame) => frame,
+                None => break Err(SwapError::NoSwapped),
+            };
+            let mut result = None;
+            if !with_locked(&frame, &mut || result = Some(self.swap_out::<T2>(&frame))) {
+                skipped.push(frame);
+                continue;
+            }
+            match result.expect("the victim is not swapped out") {
+                Ok(target) => {
+                    info!("swap out page {:#x} of token {:#x}", frame.get_virtaddr(), frame.get_token());
+                    break Ok(target);
+                }
+                // the page has been unmapped or shared since it was set swappable, try the next one
+                Err(SwapError::NotMapped) | Err(SwapError::AlreadySwapped) | Err(SwapError::Shared) => {}
+                Err(e) => break Err(e),
+            }
         };
-        info!("swap out page {}", victim.unwrap().get_virtaddr());
-        match victim {
-            None => Err(SwapError::NoSwapped),
-            Some(frame) => self.swap_out::<T2>(&frame),
+        // the skipped pages can be swapped out later
+        for frame in skipped {
+            self.swap_manager.push(frame);
         }
+        ret
     }
 
     /*
@@ -221,6 +288,12 @@ impl<T: PageTable, M: SwapManager, S: Swapper> SwapExt<T, M, S> {
                 if entry.swapped() {
                     return Err(SwapError::AlreadySwapped);
                 }
+                if !entry.present() {
+                    return Err(SwapError::NotMapped);
+                }
+                if entry.readonly_shared() || entry.writable_shared() {
+                    return Err(SwapError::Shared);
+                }
                 //assert!(!entry.swapped(), "Page already swapped!");
                 let token = swapper.swap_out(data).map_err(|_| SwapError::IOError)?;
                 //let token = swapper.swap_out(data).unwrap();
@@ -320,6 +393,7 @@ impl<T: PageTable, M: SwapManager, S: Swapper> SwapExt<T, M, S> {
     }
 }
 
+#[derive(Debug)]
 pub enum SwapError {
     /// attempt to swap out a page that is already swapped out
     AlreadySwapped,
@@ -329,6 +403,8 @@ pub enum SwapError {
     NotSwapped,
     /// there are no page to be swapped out
     NoSwapped,
+    /// attempt to swap out a page shared by copy-on-write
+    Shared,
     /// swap failed due to IO error while interact with device
     IOError,
 }
@@ -347,19 +423,20 @@ impl<T: PageTable, M: SwapManager, S: Swapper> DerefMut for SwapExt<T, M, S> {
     }
 }
 
-/*
 #[cfg(test)]
 mod test {
     use super::*;
     use super::mock_swapper::MockSwapper;
     use alloc::{sync::Arc, boxed::Box};
     use core::cell::RefCell;
-    use paging::MockPageTable;
+    use crate::paging::MockPageTable;
 
     #[derive(Debug)]
     pub enum MemOp {
         R(usize),
         W(usize),
+        /// A tick interrupt
+        Tick,
     }
 
     struct FrameAlloc(usize);
@@ -374,17 +451,49 @@ mod test {
         }
     }
 
+    /// The only page table, which is always active
+    struct MockInactivePageTable;
+
+    impl InactivePageTable for MockInactivePageTable {
+        type Active = MockPageTable;
+
+        fn new_bare() -> Self {
+            MockInactivePageTable
+        }
+        fn map_kernel(&mut self) {}
+        fn token(&self) -> usize {
+            0
+        }
+        unsafe fn set_token(_token: usize) {}
+        fn active_token() -> usize {
+            0
+        }
+        fn flush_tlb() {}
+        fn edit<T>(&mut self, _f: impl FnOnce(&mut Self::Active) -> T) -> T {
+            unimplemented!()
+        }
+    }
+
     unsafe fn clone<'a, 'b, T>(x: &'a mut T) -> &'b mut T {
         &mut *(x as *mut T)
     }
 
     /// Test framework with different SwapManagers.
+    /// There are 4 frames for the pages in [0x1000, 0x10000).
     /// See `fifo::test` mod for example.
     pub fn test_manager(swap_manager: impl 'static + SwapManager, ops: &[MemOp], pgfault_count: &[u8]) {
-        use self::MemOp::{R, W};
+        use self::MemOp::{R, W, Tick};
+        assert_eq!(ops.len(), pgfault_count.len());
         let page_fault_count = Arc::new(RefCell::new(0u8));
 
         let mut pt = SwapExt::new(MockPageTable::new(), swap_manager, MockSwapper::default());
+        // The pages are writable, but not present until the first access
+        for addr in (0x1000..0x10000).step_by(PAGE_SIZE) {
+            pt.map(addr, 0);
+            pt.unmap(addr);
+        }
+        let mut inactive = MockInactivePageTable;
+        let inactive_ptr = &mut inactive as *mut MockInactivePageTable;
 
         // Move to closure
         let pt0 = unsafe{ clone(&mut pt) };
@@ -393,22 +502,46 @@ mod test {
 
         pt.set_handler(Box::new(move |_, addr: VirtAddr| {
             *page_fault_count1.borrow_mut() += 1;
-            if pt0.page_fault_handler(addr, || alloc.alloc()) {
-                return;
-            }
-            // The page is not mapped, map it to a new frame, if no more frame, swap out.
-            let target = alloc.alloc().or_else(|| pt0.swap_out_any().ok())
+            // The page is allocated or swapped in to a new frame, if no more frame, swap out.
+            let target = alloc.alloc().or_else(|| pt0.swap_out_any::<MockInactivePageTable>().ok())
                 .expect("no more frame in both allocator and swap_manager");
-            pt0.map_to_swappable(addr, target);
+            assert!(pt0.page_fault_handler(inactive_ptr, addr, true, || target));
         }));
 
         for (op, &count) in ops.iter().zip(pgfault_count.iter()) {
             match op {
                 R(addr) => { pt.read(*addr); }
                 W(addr) => pt.write(*addr, 0),
+                Tick => pt.tick(),
             }
-            assert_eq!(*(*page_fault_count).borrow(), count);
+            assert_eq!(*(*page_fault_count).borrow(), count, "after {:?}", op);
         }
     }
+
+    #[test]
+    fn swap_out_skips_busy() {
+        use super::fifo::FifoSwapManager;
+        let mut pt = SwapExt::new(MockPageTable::new(), FifoSwapManager::default(), MockSwapper::default());
+        let mut inactive = MockInactivePageTable;
+        let inactive_ptr = &mut inactive as *mut MockInactivePageTable;
+        for addr in (0x1000..0x3000).step_by(PAGE_SIZE) {
+            pt.map(addr, addr);
+            unsafe { pt.set_swappable(inactive_ptr, addr); }
+        }
+
+        // the page table of the first page is busy, so the next one is swapped out
+        let target = pt.swap_out_any_with::<MockInactivePageTable>(|frame, swap_out| {
+            if frame.get_virtaddr() == 0x1000 {
+                return false;
+            }
+            swap_out();
+            true
+        });
+        assert_eq!(target.ok(), Some(0x2000));
+        assert!(pt.get_entry(0x2000).unwrap().swapped());
+
+        // the skipped one can be swapped out later
+        assert_eq!(pt.swap_out_any::<MockInactivePageTable>().ok(), Some(0x1000));
+        assert!(pt.swap_out_any::<MockInactivePageTable>().is_err());
+    }
 }
-*/
\ No newline at end of file
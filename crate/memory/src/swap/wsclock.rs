@@ -0,0 +1,136 @@
+//! Implement the swap manager with the WSClock (working set clock) page replacement algorithm
+//!
+//! Each page records the virtual time it was last used, which advances at each tick.
+//! The clock hand clears the accessed bits it passes, and records the time for those pages.
+//! A page not used in the last `tau` ticks is out of the working set,
+//! and the first clean one found is swapped out.
+//! If there is none in a whole round, the first dirty one out of the working set is swapped out,
+//! or else the clean page used longest ago, or else the page used longest ago.
+
+use super::*;
+use super::clock::Clock;
+
+pub struct WSClockSwapManager {
+    /// the frames with the time they were last used
+    clock: Clock<(Frame, usize)>,
+    /// the virtual time
+    time: usize,
+    /// the ticks of the working set window
+    tau: usize,
+}
+
+impl SwapManager for WSClockSwapManager {
+    fn tick<T: PageTable>(&mut self, _: &mut T) {
+        self.time += 1;
+    }
+
+    fn push(&mut self, frame: Frame) {
+        self.clock.push((frame, self.time));
+    }
+
+    fn remove(&mut self, token: usize, addr: VirtAddr) {
+        let id = self.clock
+            .position(|(x, _)| x.get_virtaddr() == addr && x.get_token() == token)
+            .expect("address not found");
+        self.clock.remove_at(id);
+    }
+
+    fn pop<T, S>(&mut self, page_table: &mut T, _: &mut S) -> Option<Frame>
+        where T: PageTable, S: Swapper
+    {
+        let clock = &mut self.clock;
+        if clock.is_empty() {
+            return None;
+        }
+        let mut old_dirty = None;
+        // (the time last used, the index) of the clean page used longest ago, and of any page
+        let mut lru_clean: Option<(usize, usize)> = None;
+        let mut lru: Option<(usize, usize)> = None;
+        for _ in 0..clock.len() {
+            let id = clock.hand();
+            let entry = match page_table.get_entry(clock[id].0.get_virtaddr()) {
+                Some(entry) => entry,
+                None => return Some(clock.remove_at(id).0),
+            };
+            if entry.accessed() {
+                entry.clear_accessed();
+                entry.update();
+                clock[id].1 = self.time;
+            }
+            let last_used = clock[id].1;
+            if self.time - last_used > self.tau {
+                if !entry.dirty() {
+                    return Some(clock.remove_at(id).0);
+                }
+                old_dirty.get_or_insert(id);
+            }
+            if !entry.dirty() && lru_clean.map_or(true, |(time, _)| last_used < time) {
+                lru_clean = Some((last_used, id));
+            }
+            if lru.map_or(true, |(time, _)| last_used < time) {
+                lru = Some((last_used, id));
+            }
+            clock.move_next();
+        }
+        let id = old_dirty
+            .or(lru_clean.map(|(_, id)| id))
+            .or(lru.map(|(_, id)| id))
+            .unwrap();
+        Some(clock.remove_at(id).0)
+    }
+}
+
+impl WSClockSwapManager {
+    /*
+    **  @brief  create a WSClock swap manager
+    **  @param  tau: usize           the ticks of the working set window
+    **  @retval WSClockSwapManager   the swap manager created
+    */
+    pub fn new(tau: usize) -> Self {
+        WSClockSwapManager {
+            clock: Clock::default(),
+            time: 0,
+            tau,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::swap::test::*;
+
+    #[test]
+    fn test() {
+        use self::MemOp::{R, W, Tick};
+        let ops = [
+            R(0x1000), R(0x2000), R(0x3000), R(0x4000),
+            R(0x5000), Tick, Tick,
+            R(0x2000), W(0x3000), R(0x1000), Tick,
+            R(0x4000), R(0x3000)];
+        let pgfault_count = [
+            1, 2, 3, 4,
+            5, 5, 5,
+            5, 5, 6, 6,
+            7, 7];
+        test_manager(WSClockSwapManager::new(1), &ops, &pgfault_count);
+    }
+
+    #[test]
+    fn old_dirty() {
+        use self::MemOp::{R, W, Tick};
+        // out of the working set, the clean 0x4000 is swapped out before the dirty 0x1000,
+        // which is swapped out when there is no clean page out of the working set
+        let ops = [
+            W(0x1000), R(0x2000), R(0x3000), R(0x4000),
+            R(0x5000), Tick, Tick,
+            R(0x3000), R(0x6000), R(0x7000),
+            R(0x3000), R(0x6000), R(0x1000)];
+        let pgfault_count = [
+            1, 2, 3, 4,
+            5, 5, 5,
+            5, 6, 7,
+            7, 7, 8];
+        test_manager(WSClockSwapManager::new(1), &ops, &pgfault_count);
+    }
+}
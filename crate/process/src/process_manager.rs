@@ -1,21 +1,35 @@
 use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::vec::Vec;
-use spin::Mutex;
+use core::ops::Index;
+use spin::{Mutex, Once};
 use log::*;
 use crate::scheduler::Scheduler;
 use crate::event_hub::EventHub;
 
 struct Process {
-    #[allow(dead_code)]
     id: Pid,
+    /// thread group id, the pid of the first thread in the group
+    tgid: Pid,
     status: Status,
     status_after_stop: Status,
     context: Option<Box<Context>>,
     parent: Pid,
     children: Vec<Pid>,
+    /// pending signals, bit `n` for signal `n`
+    signals: usize,
 }
 
 pub type Pid = usize;
+
+/// The init process, which adopts the orphans
+pub const INIT_PID: Pid = 1;
+
+/// The number of released pids held back before reusing the earliest one
+const PID_HOLD_BACK: usize = 16;
+
+/// The number of process slots allocated at a time
+const PROC_CHUNK_SIZE: usize = 32;
 type ExitCode = usize;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -37,8 +51,50 @@ pub trait Context {
     unsafe fn switch_to(&mut self, target: &mut Context);
 }
 
+/// The process table indexed by pid.
+/// It grows by chunks, and the slots never move once allocated.
+struct ProcTable {
+    chunks: Vec<Once<Vec<Mutex<Option<Process>>>>>,
+}
+
+impl ProcTable {
+    fn new(max_proc_num: usize) -> Self {
+        let chunk_num = (max_proc_num + PROC_CHUNK_SIZE - 1) / PROC_CHUNK_SIZE;
+        ProcTable {
+            chunks: (0..chunk_num).map(|_| Once::new()).collect(),
+        }
+    }
+
+    /// Get the slot of `pid`, or `None` if it has not been allocated
+    fn get(&self, pid: Pid) -> Option<&Mutex<Option<Process>>> {
+        self.chunks.get(pid / PROC_CHUNK_SIZE)?.r#try()?.get(pid % PROC_CHUNK_SIZE)
+    }
+
+    /// Allocate the slot of `pid` if not yet
+    fn grow(&self, pid: Pid) {
+        self.chunks[pid / PROC_CHUNK_SIZE].call_once(|| new_vec_default(PROC_CHUNK_SIZE));
+    }
+}
+
+impl Index<Pid> for ProcTable {
+    type Output = Mutex<Option<Process>>;
+
+    fn index(&self, pid: Pid) -> &Self::Output {
+        self.get(pid).expect("process not exist")
+    }
+}
+
+struct PidAllocator {
+    /// Released pids, the earliest first
+    free: VecDeque<Pid>,
+    /// Pids from `next` have never been used
+    next: Pid,
+}
+
 pub struct ProcessManager {
-    procs: Vec<Mutex<Option<Process>>>,
+    procs: ProcTable,
+    pids: Mutex<PidAllocator>,
+    max_proc_num: usize,
     scheduler: Mutex<Box<Scheduler>>,
     event_hub: Mutex<EventHub<Event>>,
 }
@@ -46,36 +102,60 @@ pub struct ProcessManager {
 impl ProcessManager {
     pub fn new(scheduler: Box<Scheduler>, max_proc_num: usize) -> Self {
         ProcessManager {
-            procs: new_vec_default(max_proc_num),
+            procs: ProcTable::new(max_proc_num),
+            pids: Mutex::new(PidAllocator { free: VecDeque::new(), next: 0 }),
+            max_proc_num,
             scheduler: Mutex::new(scheduler),
             event_hub: Mutex::new(EventHub::new()),
         }
     }
 
-    fn alloc_pid(&self) -> Pid {
-        for (i, proc) in self.procs.iter().enumerate() {
-            if proc.lock().is_none() {
-                return i;
-            }
+    /// Allocate a pid in O(1). Return `None` if the process number exceeded.
+    /// A released pid is not reused until `PID_HOLD_BACK` more pids are released,
+    /// unless all pids have been used.
+    fn alloc_pid(&self) -> Option<Pid> {
+        let mut pids = self.pids.lock();
+        if pids.free.len() > PID_HOLD_BACK || pids.next == self.max_proc_num {
+            return pids.free.pop_front();
         }
-        panic!("Process number exceeded");
+        let pid = pids.next;
+        pids.next += 1;
+        self.procs.grow(pid);
+        Some(pid)
+    }
+
+    /// Add a new process.
+    /// Return its pid, or `None` if the process number exceeded.
+    pub fn add(&self, context: Box<Context>, parent: Pid) -> Option<Pid> {
+        self.add_to_group(context, parent, None)
     }
 
-    /// Add a new process
-    pub fn add(&self, context: Box<Context>, parent: Pid) -> Pid {
-        let pid = self.alloc_pid();
+    /// Add a new thread to the thread group `tgid`.
+    /// It is not a child of `parent` to wait for, and is released once it exits.
+    /// Return its pid, or `None` if the process number exceeded.
+    pub fn add_thread(&self, context: Box<Context>, parent: Pid, tgid: Pid) -> Option<Pid> {
+        self.add_to_group(context, parent, Some(tgid))
+    }
+
+    /// Add a new process, which starts a new thread group if `tgid` is `None`
+    fn add_to_group(&self, context: Box<Context>, parent: Pid, tgid: Option<Pid>) -> Option<Pid> {
+        let pid = self.alloc_pid()?;
         *(&self.procs[pid]).lock() = Some(Process {
             id: pid,
+            tgid: tgid.unwrap_or(pid),
             status: Status::Ready,
             status_after_stop: Status::Ready,
             context: Some(context),
             parent,
             children: Vec::new(),
+            signals: 0,
         });
         self.scheduler.lock().insert(pid);
-        self.procs[parent].lock().as_mut().expect("invalid parent proc")
-            .children.push(pid);
-        pid
+        if tgid.is_none() {
+            self.procs[parent].lock().as_mut().expect("invalid parent proc")
+                .children.push(pid);
+        }
+        Some(pid)
     }
 
     /// Make process `pid` time slice -= 1.
@@ -119,21 +199,43 @@ impl ProcessManager {
         proc.status = proc.status_after_stop.clone();
         proc.status_after_stop = Status::Ready;
         proc.context = Some(context);
-        match proc.status {
-            Status::Ready => self.scheduler.lock().insert(pid),
-            Status::Exited(_) => self.exit_handler(pid, proc),
-            _ => {}
+        let exited = match proc.status {
+            Status::Ready => {
+                self.scheduler.lock().insert(pid);
+                false
+            }
+            Status::Exited(_) => true,
+            _ => false,
+        };
+        drop(proc_lock);
+        if exited {
+            self.exit_handler(pid);
         }
     }
 
     /// Switch the status of a process.
     /// Insert/Remove it to/from scheduler if necessary.
     fn set_status(&self, pid: Pid, status: Status) {
+        if self.update_status(pid, status) {
+            self.exit_handler(pid);
+        }
+    }
+
+    /// Switch the status of a process with its slot locked.
+    /// Return whether it has exited now.
+    fn update_status(&self, pid: Pid, status: Status) -> bool {
         let mut proc_lock = self.procs[pid].lock();
-        let mut proc = proc_lock.as_mut().expect("process not exist");
+        let proc = proc_lock.as_mut().expect("process not exist");
+        self.update_proc_status(proc, status)
+    }
+
+    /// Switch the status of the locked process `proc`.
+    /// Return whether it has exited now.
+    fn update_proc_status(&self, proc: &mut Process, status: Status) -> bool {
+        let pid = proc.id;
         trace!("process {} {:?} -> {:?}", pid, proc.status, status);
         match (&proc.status, &status) {
-            (Status::Ready, Status::Ready) => return,
+            (Status::Ready, Status::Ready) => return false,
             (Status::Ready, _) => self.scheduler.lock().remove(pid),
             (Status::Exited(_), _) => panic!("can not set status for a exited process"),
             (Status::Sleeping, Status::Exited(_)) => self.event_hub.lock().remove(Event::Wakeup(pid)),
@@ -145,33 +247,63 @@ impl ProcessManager {
             _ => proc.status = status,
         }
         match proc.status {
-            Status::Exited(_) => self.exit_handler(pid, proc),
-            _ => {}
+            Status::Exited(_) => true,
+            _ => false,
         }
     }
 
     pub fn get_status(&self, pid: Pid) -> Option<Status> {
-        self.procs[pid].lock().as_ref().map(|p| p.status.clone())
+        self.procs.get(pid)?.lock().as_ref().map(|p| p.status.clone())
     }
 
-    /// Remove an exited proc `pid`.
-    /// Its all children will be set parent to 0.
-    pub fn remove(&self, pid: Pid) {
-        let mut proc_lock = self.procs[pid].lock();
-        let proc = proc_lock.as_ref().expect("process not exist");
-        match proc.status {
-            Status::Exited(_) => {}
-            _ => panic!("can not remove non-exited process"),
+    /// Get the thread group id of process `pid`
+    pub fn get_tgid(&self, pid: Pid) -> Option<Pid> {
+        self.procs.get(pid)?.lock().as_ref().map(|p| p.tgid)
+    }
+
+    /// Send signal `sig` to process `pid`.
+    /// It is handled when the process returns to user mode,
+    /// so a sleeping process handles it after it is woken up by what it waits for.
+    pub fn send_signal(&self, pid: Pid, sig: usize) {
+        if let Some(proc) = self.procs[pid].lock().as_mut() {
+            proc.signals |= 1 << sig;
         }
-        // orphan procs
-        for child in proc.children.iter() {
-            (&self.procs[*child]).lock().as_mut().expect("process not exist").parent = 0;
+    }
+
+    /// Take out a pending signal of process `pid` which is not in `mask`.
+    /// The lowest signal number is taken first.
+    pub fn take_signal(&self, pid: Pid, mask: usize) -> Option<usize> {
+        let mut proc_lock = self.procs[pid].lock();
+        let proc = proc_lock.as_mut().expect("process not exist");
+        let pending = proc.signals & !mask;
+        if pending == 0 {
+            return None;
         }
-        // remove self from parent's children list
-        self.procs[proc.parent].lock().as_mut().expect("process not exist")
+        let sig = pending.trailing_zeros() as usize;
+        proc.signals &= !(1 << sig);
+        Some(sig)
+    }
+
+    /// Remove an exited proc `pid`. Called by its parent.
+    /// Its children have been given to the init process when it exited.
+    pub fn remove(&self, pid: Pid) {
+        // only one slot is locked at a time, see `exit_handler`
+        let parent = {
+            let proc_lock = self.procs[pid].lock();
+            let proc = proc_lock.as_ref().expect("process not exist");
+            match proc.status {
+                Status::Exited(_) => {}
+                _ => panic!("can not remove non-exited process"),
+            }
+            proc.parent
+        };
+        // remove self from parent's children list.
+        // The parent is the caller, so it can't exit and give self to init meanwhile.
+        self.procs[parent].lock().as_mut().expect("process not exist")
             .children.retain(|&i| i != pid);
         // release the pid
-        *proc_lock = None;
+        *self.procs[pid].lock() = None;
+        self.pids.lock().free.push_back(pid);
     }
 
     /// Sleep `pid` for `time` ticks.
@@ -202,16 +334,64 @@ impl ProcessManager {
         // NOTE: if `pid` is running, status change will be deferred.
         self.set_status(pid, Status::Exited(code));
     }
-    /// Called when a process exit
-    fn exit_handler(&self, pid: Pid, proc: &mut Process) {
-        // wakeup parent if waiting
-        let parent = proc.parent;
+
+    /// Exit all the threads in the thread group of process `pid`
+    pub fn exit_group(&self, pid: Pid, code: ExitCode) {
+        let tgid = self.get_tgid(pid).expect("process not exist");
+        for i in 0..self.max_proc_num {
+            // a thread may exit by itself meanwhile, so check and exit it under the lock
+            let exited = match self.procs.get(i) {
+                Some(slot) => match slot.lock().as_mut() {
+                    Some(proc) if proc.tgid == tgid => match proc.status {
+                        Status::Exited(_) => false,
+                        _ => self.update_proc_status(proc, Status::Exited(code)),
+                    },
+                    _ => false,
+                },
+                None => false,
+            };
+            if exited {
+                self.exit_handler(i);
+            }
+        }
+    }
+    /// Called when a process exit.
+    /// It must be called with no slot locked, and it locks only one slot at a time,
+    /// since an exiting child locks its parent's slot and vice versa.
+    fn exit_handler(&self, pid: Pid) {
+        let (parent, children, context, is_thread) = {
+            let mut proc_lock = self.procs[pid].lock();
+            let proc = proc_lock.as_mut().expect("process not exist");
+            (proc.parent, core::mem::replace(&mut proc.children, Vec::new()), proc.context.take(), proc.tgid != pid)
+        };
+        // give the orphans to init, and notify it of the exited ones
+        for child in children {
+            self.procs[child].lock().as_mut().expect("process not exist").parent = INIT_PID;
+            self.procs[INIT_PID].lock().as_mut().expect("init process not exist")
+                .children.push(child);
+            // a child exiting from now on notifies init by itself
+            if let Some(Status::Exited(_)) = self.get_status(child) {
+                self.notify_exit(INIT_PID, child);
+            }
+        }
+        if is_thread {
+            // no one waits for a thread, so release it at once
+            drop(context);
+            *self.procs[pid].lock() = None;
+            self.pids.lock().free.push_back(pid);
+            return;
+        }
+        self.notify_exit(parent, pid);
+        // drop its context
+        drop(context);
+    }
+
+    /// Wake up `parent` if it is waiting for its child `pid` to exit
+    fn notify_exit(&self, parent: Pid, pid: Pid) {
         match self.get_status(parent).expect("process not exist") {
             Status::Waiting(target) if target == pid || target == 0 => self.wakeup(parent),
             _ => {}
         }
-        // drop its context
-        proc.context = None;
     }
 }
 
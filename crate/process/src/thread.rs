@@ -46,10 +46,11 @@ pub fn sleep(dur: Duration) {
 }
 
 /// Spawns a new thread, returning a JoinHandle for it.
+/// Returns Err if the process number is exceeded.
 ///
 /// `F`: Type of the function `f`
 /// `T`: Type of the return value of `f`
-pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+pub fn spawn<F, T>(f: F) -> Result<JoinHandle<T>, ()>
     where
         F: Send + 'static + FnOnce() -> T,
         T: Send + 'static,
@@ -92,14 +93,21 @@ pub fn spawn<F, T>(f: F) -> JoinHandle<T>
 
     // 在Processor中创建新的线程
     let context = new_kernel_context(kernel_thread_entry::<F, T>, f as usize);
-    let pid = processor().manager().add(context, 0);
+    let pid = match processor().manager().add(context, 0) {
+        Some(pid) => pid,
+        None => {
+            // 线程没有创建成功，回收堆上的函数f
+            drop(unsafe { Box::from_raw(f) });
+            return Err(());
+        }
+    };
 
     // 接下来看看`JoinHandle::join()`的实现
     // 了解是如何获取f返回值的
-    return JoinHandle {
+    return Ok(JoinHandle {
         thread: Thread { pid },
         mark: PhantomData,
-    };
+    });
 }
 
 /// Cooperatively gives up a timeslice to the OS scheduler.
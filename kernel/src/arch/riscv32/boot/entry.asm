@@ -17,3 +17,16 @@ bootstack:
     .space 4096 * 16 * 8
     .global bootstacktop
 bootstacktop:
+
+    # in .bss, which is mapped by `remap_the_kernel` unlike the space after bootstacktop
+    .section .bss
+    .align 12  #PGSHIFT
+    # a 16K stack per hart to handle kernel stack overflows
+    .global kstack_overflow_stack
+kstack_overflow_stack:
+    .space 4096 * 4 * 8
+
+    # a scratch slot of 2 words per hart for `CHECK_KERNEL_STACK`
+    .global kstack_check_scratch
+kstack_check_scratch:
+    .space 8 * 2 * 8
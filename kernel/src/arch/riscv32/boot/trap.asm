@@ -9,6 +9,46 @@
 #   LOAD
 #   STORE
 #   TEST_BACK_TO_KERNEL
+#   KSTACK_OFFSET
+
+# If the trap frame below sp would be in the guard of a kernel stack, the stack overflows.
+# Switch to the overflow stack of this hart, so that the trap can be handled and the thread killed.
+# Kernel stack slots are 64K with the lower half as the guard, 256 slots from KSTACK_OFFSET.
+# sscratch = previous-sp is kept. t0 and the hartid are saved in the scratch slot of this hart,
+# while tp points to the slot.
+.macro CHECK_KERNEL_STACK
+    csrw (xscratch), t0         # sscratch = t0 for a while, sp = previous-sp
+    lui t0, %hi(kstack_check_scratch)
+    addi t0, t0, %lo(kstack_check_scratch)
+    slli tp, tp, XLENB_SHIFT + 1
+    add tp, tp, t0              # tp = the scratch slot of this hart
+    sub t0, tp, t0
+    srli t0, t0, XLENB_SHIFT + 1
+    STORE_TP t0, 1              # save hartid
+    csrr t0, (xscratch)
+    STORE_TP t0, 0              # save t0
+    csrw (xscratch), sp         # sscratch = previous-sp
+    li t0, KSTACK_OFFSET
+    sub t0, sp, t0
+    addi t0, t0, -36 * XLENB
+    srli t0, t0, 24
+    bnez t0, 1f         # not in the kernel stack region
+    li t0, KSTACK_OFFSET
+    sub t0, sp, t0
+    addi t0, t0, -36 * XLENB
+    srli t0, t0, 15
+    andi t0, t0, 1
+    bnez t0, 1f         # in the upper half of a slot
+    LOAD_TP t0, 1
+    addi t0, t0, 1
+    slli t0, t0, 14
+    lui sp, %hi(kstack_overflow_stack)
+    addi sp, sp, %lo(kstack_overflow_stack)
+    add sp, sp, t0
+1:
+    LOAD_TP t0, 0               # restore t0
+    LOAD_TP tp, 1               # restore hartid
+.endm
 
 .macro SAVE_ALL
     # If coming from userspace, preserve the user stack pointer and load
@@ -19,6 +59,7 @@
 _restore_kernel_sp:
     csrr sp, (xscratch)
     # sscratch = previous-sp, sp = kernel-sp
+    CHECK_KERNEL_STACK
 _save_context:
     # provide room for trap frame
     addi sp, sp, -36 * XLENB
@@ -21,11 +21,25 @@ pub const KERNEL_P2_INDEX: usize = 0x8000_0000 >> 12 >> 10;
 #[cfg(target_arch = "riscv64")]
 pub const KERNEL_P4_INDEX: usize = 0x0000_FFFF_8000_0000 >> 12 >> 9 >> 9 >> 9;
 
+// Kernel stacks are mapped in slots of this region, with the stack in the upper half of each slot
+// and the lower half unmapped as a guard. `CHECK_KERNEL_STACK` in `trap.asm` depends on the layout.
+#[cfg(target_arch = "riscv32")]
+pub const KERNEL_STACK_OFFSET: usize = 0xC000_0000;
+#[cfg(target_arch = "riscv64")]
+pub const KERNEL_STACK_OFFSET: usize = 0xFFFF_FFFF_C000_0000;
+pub const KERNEL_STACK_SLOT_SIZE: usize = 0x10000;
+pub const KERNEL_STACK_NUM: usize = 256;
+
 #[cfg(feature = "board_k210")]
 pub const KERNEL_HEAP_SIZE: usize = 0x0010_0000;
 #[cfg(not(feature = "board_k210"))]
 pub const KERNEL_HEAP_SIZE: usize = 0x00a0_0000;
 
+#[cfg(feature = "board_k210")]
+pub const SWAP_SIZE: usize = 0x0004_0000;
+#[cfg(not(feature = "board_k210"))]
+pub const SWAP_SIZE: usize = 0x0010_0000;
+
 #[cfg(feature = "board_k210")]
 pub const MEMORY_OFFSET: usize = 0x4000_0000;
 #[cfg(target_arch = "riscv32")]
@@ -40,6 +54,15 @@ pub const MEMORY_END: usize = 0x4060_0000;
 #[cfg(all(target_arch = "riscv64", not(feature = "board_k210")))]
 pub const MEMORY_END: usize = 0x8100_0000;
 
+pub const USER_PIE_OFFSET: usize = 0x00400000;
+pub const USER_MMAP_OFFSET: usize = 0x10000000;
+pub const USER_INTERP_OFFSET: usize = 0x60000000;
 pub const USER_STACK_OFFSET: usize = 0x70000000;
+/// The unmapped gap between `USER_STACK_OFFSET` and the user stack, where an overflow faults
+pub const USER_STACK_GUARD_SIZE: usize = 0x10000;
+/// The max size of the user stack, whose pages are allocated on demand
+#[cfg(feature = "board_k210")]
 pub const USER_STACK_SIZE: usize = 0x10000;
+#[cfg(not(feature = "board_k210"))]
+pub const USER_STACK_SIZE: usize = 0x100000;
 pub const USER32_STACK_OFFSET: usize = USER_STACK_OFFSET;
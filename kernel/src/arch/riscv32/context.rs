@@ -74,6 +74,17 @@ impl TrapFrame {
     }
 }
 
+impl TrapFrame {
+    /// Whether the trap is from user mode
+    pub fn is_user(&self) -> bool {
+        #[cfg(feature = "m_mode")]
+        let user = match self.sstatus.mpp() { xstatus::MPP::User => true, _ => false };
+        #[cfg(not(feature = "m_mode"))]
+        let user = match self.sstatus.spp() { xstatus::SPP::User => true, _ => false };
+        user
+    }
+}
+
 use core::fmt::{Debug, Formatter, Error};
 impl Debug for TrapFrame {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
@@ -265,21 +276,25 @@ impl Context {
 
     /*
     * @param:
-    *   TrapFrame: the trapframe of the forked process(thread)
+    *   TrapFrame: the trapframe of the cloned process(thread)
+    *   ustack_top: user stack top of the new thread, 0 to keep the caller's stack pointer
     *   kstack_top: kernel stack top
     *   cr3: cr3 register, save the physical address of Page directory
     * @brief:
-    *   fork and generate a new process(thread) Context according to the TrapFrame and save it's address at kernel stack top - 1
+    *   fork or clone and generate a new process(thread) Context according to the TrapFrame and save it's address at kernel stack top - 1
     * @retval:
     *   a Context struct with the pointer to the kernel stack top - 1 as its only element
     */
-    pub unsafe fn new_fork(tf: &TrapFrame, kstack_top: usize, cr3: usize) -> Self {
+    pub unsafe fn new_clone(tf: &TrapFrame, ustack_top: usize, kstack_top: usize, cr3: usize) -> Self {
         InitStack {
             context: ContextData::new(cr3),
             tf: {
                 let mut tf = tf.clone();
-                // fork function's ret value, the new process is 0
+                // fork/clone function's ret value, the new process is 0
                 tf.x[10] = 0; // a0
+                if ustack_top != 0 {
+                    tf.x[2] = ustack_top; // sp
+                }
                 tf
             },
         }.push_at(kstack_top)
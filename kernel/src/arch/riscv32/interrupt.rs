@@ -107,6 +107,9 @@ pub extern fn rust_trap(tf: &mut TrapFrame) {
         Trap::Exception(E::InstructionPageFault) => page_fault(tf),
         _ => crate::trap::error(tf),
     }
+    if tf.is_user() {
+        crate::signal::handle_signals(tf);
+    }
     trace!("Interrupt end");
 }
 
@@ -151,10 +154,17 @@ fn syscall(tf: &mut TrapFrame) {
 *   TrapFrame: the Trapframe for the illegal inst exception
 * @brief:
 *   process IllegalInstruction exception
+*   M-mode: emulate the instruction by BBL
+*   S-mode: BBL has failed to emulate it, so send SIGILL
 */
 fn illegal_inst(tf: &mut TrapFrame) {
-    (super::BBL.illegal_insn_trap)(tf.x.as_ptr(), tf.scause.bits(), tf.sepc);
-    tf.sepc = mepc::read();
+    #[cfg(feature = "m_mode")]
+    {
+        (super::BBL.illegal_insn_trap)(tf.x.as_ptr(), tf.scause.bits(), tf.sepc);
+        tf.sepc = mepc::read();
+    }
+    #[cfg(not(feature = "m_mode"))]
+    crate::trap::fault(tf, crate::signal::SIGILL);
 }
 
 /*
@@ -167,7 +177,11 @@ fn page_fault(tf: &mut TrapFrame) {
     let addr = tf.stval;
     trace!("\nEXCEPTION: Page Fault @ {:#x}", addr);
 
-    if !crate::memory::page_fault_handler(addr) {
+    if !tf.is_user() && crate::memory::is_kernel_stack_guard(addr) {
+        error!("kernel stack overflow in pid {}", crate::process::processor().pid());
         crate::trap::error(tf);
     }
+    if !crate::memory::page_fault_handler(addr) {
+        crate::trap::fault(tf, crate::signal::SIGSEGV);
+    }
 }
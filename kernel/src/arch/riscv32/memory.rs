@@ -31,6 +31,8 @@ pub fn init() {
     // remap the kernel use 4K page
     remap_the_kernel();
     info!("remap_the_kernel end");
+    init_kernel_stack_region();
+    info!("init_kernel_stack_region end");
 }
 
 pub fn init_other() {
@@ -74,16 +76,30 @@ fn init_frame_allocator() {
 fn remap_the_kernel() {
     let offset = -(super::consts::KERN_VA_BASE as isize);
     let mut ms = MemorySet::new_bare();
-    ms.push(stext as usize, etext as usize, Linear::new(offset, MemoryAttr::default().execute().readonly()), "text");
-    ms.push(sdata as usize, edata as usize, Linear::new(offset, MemoryAttr::default()), "data");
-    ms.push(srodata as usize, erodata as usize, Linear::new(offset, MemoryAttr::default().readonly()), "rodata");
-    ms.push(bootstack as usize, bootstacktop as usize, Linear::new(offset, MemoryAttr::default()), "stack");
-    ms.push(sbss as usize, ebss as usize, Linear::new(offset, MemoryAttr::default()), "bss");
+    ms.push(stext as usize, etext as usize, MemoryAttr::default().execute().readonly(), Linear::new(offset), "text");
+    ms.push(sdata as usize, edata as usize, MemoryAttr::default(), Linear::new(offset), "data");
+    ms.push(srodata as usize, erodata as usize, MemoryAttr::default().readonly(), Linear::new(offset), "rodata");
+    ms.push(bootstack as usize, bootstacktop as usize, MemoryAttr::default(), Linear::new(offset), "stack");
+    ms.push(sbss as usize, ebss as usize, MemoryAttr::default(), Linear::new(offset), "bss");
     unsafe { ms.activate(); }
     unsafe { SATP = ms.token(); }
     mem::forget(ms);
 }
 
+/// Create the page tables of the kernel stack region, which are shared by all page tables
+#[cfg(not(feature = "no_mmu"))]
+fn init_kernel_stack_region() {
+    use crate::consts::{KERNEL_STACK_OFFSET, KERNEL_STACK_SLOT_SIZE, KERNEL_STACK_NUM};
+    use crate::memory::active_table;
+    let region_end = KERNEL_STACK_OFFSET + KERNEL_STACK_NUM * KERNEL_STACK_SLOT_SIZE;
+    let mut table = active_table();
+    // a P1 table covers 4M on riscv32 and 2M on riscv64
+    for addr in (KERNEL_STACK_OFFSET..region_end).step_by(0x200000) {
+        table.map(addr, 0);
+        table.unmap(addr);
+    }
+}
+
 // First core stores its SATP here.
 // Other cores load it later.
 static mut SATP: usize = 0;
@@ -74,6 +74,8 @@ global_asm!("
 #[cfg(target_arch = "riscv32")]
 global_asm!(r"
     .equ XLENB,     4
+    .equ XLENB_SHIFT, 2
+    .equ KSTACK_OFFSET, 0xC0000000  // KERNEL_STACK_OFFSET
     .equ XLENb,     32
     .macro LOAD a1, a2
         lw \a1, \a2*XLENB(sp)
@@ -81,10 +83,18 @@ global_asm!(r"
     .macro STORE a1, a2
         sw \a1, \a2*XLENB(sp)
     .endm
+    .macro LOAD_TP a1, a2
+        lw \a1, \a2*XLENB(tp)
+    .endm
+    .macro STORE_TP a1, a2
+        sw \a1, \a2*XLENB(tp)
+    .endm
 ");
 #[cfg(target_arch = "riscv64")]
 global_asm!(r"
     .equ XLENB,     8
+    .equ XLENB_SHIFT, 3
+    .equ KSTACK_OFFSET, 0xFFFFFFFFC0000000  // KERNEL_STACK_OFFSET
     .equ XLENb,     64
     .macro LOAD a1, a2
         ld \a1, \a2*XLENB(sp)
@@ -92,6 +102,12 @@ global_asm!(r"
     .macro STORE a1, a2
         sd \a1, \a2*XLENB(sp)
     .endm
+    .macro LOAD_TP a1, a2
+        ld \a1, \a2*XLENB(tp)
+    .endm
+    .macro STORE_TP a1, a2
+        sd \a1, \a2*XLENB(tp)
+    .endm
 ");
 
 
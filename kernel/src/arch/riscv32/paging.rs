@@ -7,9 +7,12 @@ use riscv::paging::{Mapper, PageTable as RvPageTable, PageTableEntry, PageTableF
 use riscv::paging::{FrameAllocator, FrameDeallocator};
 use riscv::register::satp;
 use rcore_memory::paging::*;
+use rcore_memory::cow::CowExt;
 use log::*;
 #[cfg(target_arch = "riscv32")]
-use crate::consts::KERNEL_P2_INDEX;
+use crate::consts::{KERNEL_P2_INDEX, KERNEL_STACK_OFFSET, KERNEL_STACK_SLOT_SIZE, KERNEL_STACK_NUM, MAX_CPU_NUM};
+#[cfg(target_arch = "riscv32")]
+use alloc::vec::Vec;
 #[cfg(target_arch = "riscv64")]
 use crate::consts::KERNEL_P4_INDEX;
 
@@ -218,13 +221,22 @@ impl Entry for PageEntry {
     fn set_mmio(&mut self, _value: u8) { }
 }
 
+/// The token of the active page table of each hart whose recursive entry is redirected by `edit`, or 0.
+/// The entries of the page table can't be reached by the recursive mapping meanwhile.
+static mut REDIRECTED_TOKEN: [usize; MAX_CPU_NUM] = [0; MAX_CPU_NUM];
+
+/// The token of the active page table of this hart if it is redirected by `edit`, or 0
+pub fn redirected_token() -> usize {
+    unsafe { REDIRECTED_TOKEN[super::cpu::id()] }
+}
+
 #[derive(Debug)]
 pub struct InactivePageTable0 {
     root_frame: Frame,
 }
 
 impl InactivePageTable for InactivePageTable0 {
-    type Active = ActivePageTable;
+    type Active = CowExt<ActivePageTable>;
 
     fn new_bare() -> Self {
         let target = alloc_frame().expect("failed to allocate frame");
@@ -247,18 +259,20 @@ impl InactivePageTable for InactivePageTable0 {
             fn start();
             fn end();
         }
-        let mut entrys: [PageTableEntry; 16] = unsafe { core::mem::uninitialized() };
-        let entry_start = start as usize >> 22;
-        let entry_end = (end as usize >> 22) + 1;
-        let entry_count = entry_end - entry_start;
-        for i in 0..entry_count {
-            entrys[i] = table[entry_start + i];
-        }
+        // the kernel, and the kernel stack region whose page tables are created at boot
+        let ranges = [
+            (start as usize >> 22, (end as usize >> 22) + 1),
+            (KERNEL_STACK_OFFSET >> 22, ((KERNEL_STACK_OFFSET + KERNEL_STACK_NUM * KERNEL_STACK_SLOT_SIZE - 1) >> 22) + 1),
+        ];
+        let entrys: Vec<(usize, PageTableEntry)> = ranges.iter()
+            .flat_map(|&(entry_start, entry_end)| entry_start..entry_end)
+            .map(|i| (i, table[i]))
+            .collect();
 
         self.edit(|_| {
             // NOTE: 'table' now refers to new page table
-            for i in 0..entry_count {
-                table[entry_start + i] = entrys[i];
+            for &(i, entry) in entrys.iter() {
+                table[i] = entry;
             }
         });
     }
@@ -308,17 +322,26 @@ impl InactivePageTable for InactivePageTable0 {
     */
     fn edit<T>(&mut self, f: impl FnOnce(&mut Self::Active) -> T) -> T {
         let target = satp::read().frame().start_address().as_usize();
+        let active_token = Self::active_token();
+        let redirected = active_token != self.token();
+        let cpu_id = super::cpu::id();
         active_table().with_temporary_map(target, |active_table, root_table: &mut RvPageTable| {
             let backup = root_table[RECURSIVE_INDEX].clone();
 
             // overwrite recursive mapping
             root_table[RECURSIVE_INDEX].set(self.root_frame.clone(), EF::VALID);
             sfence_vma_all();
+            // `edit` may be nested, e.g. swapping out a page while editing another page table
+            let prev_redirected = unsafe { REDIRECTED_TOKEN[cpu_id] };
+            if redirected {
+                unsafe { REDIRECTED_TOKEN[cpu_id] = active_token; }
+            }
 
             // execute f in the new context
             let ret = f(active_table);
 
             // restore recursive mapping to original p2 table
+            unsafe { REDIRECTED_TOKEN[cpu_id] = prev_redirected; }
             root_table[RECURSIVE_INDEX] = backup;
             sfence_vma_all();
 
@@ -329,6 +352,8 @@ impl InactivePageTable for InactivePageTable0 {
 
 impl Drop for InactivePageTable0 {
     fn drop(&mut self) {
+        #[cfg(not(feature = "no_mmu"))]
+        crate::swap::forget_table(self.token());
         dealloc_frame(self.root_frame.start_address().as_usize());
     }
 }
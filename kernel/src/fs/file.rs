@@ -0,0 +1,96 @@
+//! File handle for process
+
+use alloc::{sync::Arc, string::String};
+use simple_filesystem::{INode, FileInfo, FsError, Result};
+use super::Pipe;
+
+/// An opened file, with its own offset.
+#[derive(Clone)]
+pub struct FileHandle {
+    inode: Arc<INode>,
+    offset: usize,
+    options: OpenOptions,
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    pub read: bool,
+    pub write: bool,
+    /// Before each write, the offset is positioned at the end of the file
+    pub append: bool,
+}
+
+#[derive(Debug)]
+pub enum SeekFrom {
+    Start(usize),
+    End(isize),
+    Current(isize),
+}
+
+impl FileHandle {
+    pub fn new(inode: Arc<INode>, options: OpenOptions) -> Self {
+        FileHandle { inode, offset: 0, options }
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if !self.options.read {
+            return Err(FsError::InvalidParam);
+        }
+        let len = self.inode.read_at(self.offset, buf)?;
+        self.offset += len;
+        Ok(len)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if !self.options.write {
+            return Err(FsError::InvalidParam);
+        }
+        if self.options.append {
+            self.offset = self.inode.info()?.size;
+        }
+        let len = self.inode.write_at(self.offset, buf)?;
+        self.offset += len;
+        Ok(len)
+    }
+
+    /// Move the offset. Return the new offset from the start of the file.
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<usize> {
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => add_offset(self.inode.info()?.size, offset),
+            SeekFrom::Current(offset) => add_offset(self.offset, offset),
+        };
+        self.offset = offset.ok_or(FsError::InvalidParam)?;
+        Ok(self.offset)
+    }
+
+    /// Whether the file is an end of a pipe, which has no offset
+    pub fn is_pipe(&self) -> bool {
+        self.inode.as_any_ref().is::<Pipe>()
+    }
+
+    /// Whether the file is the write end of a pipe whose read end is closed
+    pub fn is_broken_pipe(&self) -> bool {
+        self.inode.as_any_ref().downcast_ref::<Pipe>().map_or(false, |pipe| pipe.is_broken())
+    }
+
+    /// Write the data of the file to the device.
+    pub fn sync(&self) -> Result<()> {
+        self.inode.sync()
+    }
+
+    pub fn info(&self) -> Result<FileInfo> {
+        self.inode.info()
+    }
+
+    pub fn get_entry(&self, id: usize) -> Result<String> {
+        self.inode.get_entry(id)
+    }
+}
+
+/// Add a signed `offset` to `base`. Return `None` if the result is negative.
+fn add_offset(base: usize, offset: isize) -> Option<usize> {
+    (base as isize).checked_add(offset)
+        .filter(|&offset| offset >= 0)
+        .map(|offset| offset as usize)
+}
@@ -7,6 +7,11 @@ use crate::arch::driver::ide;
 use crate::sync::Condvar;
 use crate::sync::SpinNoIrqLock as Mutex;
 
+pub use self::file::{FileHandle, OpenOptions, SeekFrom};
+pub use self::pipe::Pipe;
+
+mod file;
+
 lazy_static! {
     pub static ref ROOT_INODE: Arc<INode> = {
         #[cfg(any(target_arch = "riscv32", target_arch = "riscv64", target_arch = "aarch64"))]
@@ -123,6 +128,9 @@ macro_rules! impl_inode {
     };
 }
 
+// after `impl_inode`, which is used by it
+mod pipe;
+
 impl INode for Stdin {
     fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
         buf[0] = self.pop() as u8;
@@ -144,6 +152,42 @@ impl INode for Stdout {
     impl_inode!();
 }
 
+/// Find the inode at `path`, which is relative to the directory `cwd` unless it is absolute.
+/// `..` is looked up in each directory like the other names.
+pub fn lookup(cwd: &Arc<INode>, path: &str) -> Result<Arc<INode>> {
+    let mut inode = match path.starts_with('/') {
+        true => ROOT_INODE.clone(),
+        false => cwd.clone(),
+    };
+    for name in path.split('/').filter(|&name| name != "" && name != ".") {
+        inode = inode.find(name)?;
+    }
+    Ok(inode)
+}
+
+/// Resolve `path` against the working directory `cwd`, handling `.` and `..`.
+/// Return the normalized absolute path, e.g. "/a/b".
+pub fn resolve_path(cwd: &str, path: &str) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    let base = if path.starts_with('/') { "" } else { cwd };
+    for name in base.split('/').chain(path.split('/')) {
+        match name {
+            "" | "." => {}
+            ".." => { names.pop(); }
+            _ => names.push(name),
+        }
+    }
+    let mut result = String::new();
+    for name in names {
+        result.push('/');
+        result.push_str(name);
+    }
+    if result.is_empty() {
+        result.push('/');
+    }
+    result
+}
+
 pub trait INodeExt {
     fn read_as_vec(&self) -> Result<Vec<u8>>;
 }
@@ -157,3 +201,13 @@ impl INodeExt for INode {
         Ok(buf)
     }
 }
+
+/// An INode which can be mapped into memory by the `File` handler
+#[derive(Clone)]
+pub struct INodeForMap(pub Arc<INode>);
+
+impl rcore_memory::memory_set::handler::Read for INodeForMap {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, ()> {
+        self.0.read_at(offset, buf).map_err(|_| ())
+    }
+}
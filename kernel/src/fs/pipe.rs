@@ -0,0 +1,114 @@
+//! Pipe between processes
+
+use alloc::{sync::Arc, string::String, collections::VecDeque};
+use core::any::Any;
+use simple_filesystem::*;
+use crate::sync::Condvar;
+use crate::sync::SpinNoIrqLock as Mutex;
+
+/// The capacity of the pipe buffer
+const PIPE_SIZE: usize = 4096;
+
+/// One end of a pipe.
+/// The end is closed when it is dropped, i.e. all files opening it are closed.
+pub struct Pipe {
+    inner: Arc<PipeInner>,
+    end: PipeEnd,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum PipeEnd {
+    Read,
+    Write,
+}
+
+struct PipeInner {
+    buf: Mutex<PipeBuf>,
+    /// Notified when data is written or the write end is closed
+    readable: Condvar,
+    /// Notified when data is read or the read end is closed
+    writable: Condvar,
+}
+
+#[derive(Default)]
+struct PipeBuf {
+    data: VecDeque<u8>,
+    read_closed: bool,
+    write_closed: bool,
+}
+
+impl Pipe {
+    /// Create a pipe. Return the read end and the write end.
+    pub fn new_pair() -> (Pipe, Pipe) {
+        let inner = Arc::new(PipeInner {
+            buf: Mutex::new(PipeBuf::default()),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+        });
+        let read = Pipe { inner: inner.clone(), end: PipeEnd::Read };
+        let write = Pipe { inner, end: PipeEnd::Write };
+        (read, write)
+    }
+
+    /// Whether it is the write end and the read end is closed
+    pub fn is_broken(&self) -> bool {
+        self.end == PipeEnd::Write && self.inner.buf.lock().read_closed
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let mut buf = self.inner.buf.lock();
+        match self.end {
+            PipeEnd::Read => buf.read_closed = true,
+            PipeEnd::Write => buf.write_closed = true,
+        }
+        self.inner.readable.notify_all();
+        self.inner.writable.notify_all();
+    }
+}
+
+impl INode for Pipe {
+    /// Block until some data is available. Return 0 if the write end is closed.
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if self.end != PipeEnd::Read {
+            return Err(FsError::InvalidParam);
+        }
+        let mut pipe_buf = self.inner.buf.lock();
+        while pipe_buf.data.is_empty() && !pipe_buf.write_closed {
+            pipe_buf = self.inner.readable.wait(pipe_buf);
+        }
+        let len = buf.len().min(pipe_buf.data.len());
+        for (dst, src) in buf.iter_mut().zip(pipe_buf.data.drain(..len)) {
+            *dst = src;
+        }
+        self.inner.writable.notify_all();
+        Ok(len)
+    }
+    /// Block until all data is written.
+    /// Writing to a pipe whose read end is closed fails with `InvalidParam`,
+    /// and the caller should raise SIGPIPE for the broken pipe.
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        if self.end != PipeEnd::Write {
+            return Err(FsError::InvalidParam);
+        }
+        let mut written = 0;
+        let mut pipe_buf = self.inner.buf.lock();
+        loop {
+            if pipe_buf.read_closed {
+                return Err(FsError::InvalidParam);
+            }
+            let len = (buf.len() - written).min(PIPE_SIZE - pipe_buf.data.len());
+            pipe_buf.data.extend(&buf[written..written + len]);
+            written += len;
+            if len != 0 {
+                self.inner.readable.notify_all();
+            }
+            if written == buf.len() {
+                return Ok(written);
+            }
+            pipe_buf = self.inner.writable.wait(pipe_buf);
+        }
+    }
+    impl_inode!();
+}
@@ -19,6 +19,8 @@ use linked_list_allocator::LockedHeap;
 #[macro_use]    // print!
 mod logging;
 mod memory;
+#[cfg(not(feature = "no_mmu"))]
+mod swap;
 mod lang;
 mod util;
 mod consts;
@@ -27,6 +29,7 @@ mod syscall;
 mod fs;
 mod sync;
 mod trap;
+mod signal;
 mod shell;
 
 #[allow(dead_code)]
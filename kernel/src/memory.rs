@@ -1,6 +1,6 @@
 pub use crate::arch::paging::*;
 use bit_allocator::BitAlloc;
-use crate::consts::MEMORY_OFFSET;
+use crate::consts::{MEMORY_OFFSET, KERNEL_STACK_OFFSET, KERNEL_STACK_SLOT_SIZE, KERNEL_STACK_NUM};
 use super::HEAP_ALLOCATOR;
 use rcore_memory::*;
 use rcore_memory::cow::CowExt;
@@ -53,8 +53,10 @@ impl FrameAllocator for GlobalFrameAlloc {
         // get the real address of the alloc frame
         let ret = FRAME_ALLOCATOR.lock().alloc().map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
         trace!("Allocate frame: {:x?}", ret);
+        // swap out a user page when the frames are exhausted
+        #[cfg(not(feature = "no_mmu"))]
+        let ret = ret.or_else(crate::swap::swap_out_any);
         ret
-        // TODO: try to swap out when alloc failed
     }
     fn dealloc(&self, target: usize) {
         trace!("Deallocate frame: {:x}", target);
@@ -69,20 +71,89 @@ pub fn dealloc_frame(target: usize) {
     GlobalFrameAlloc.dealloc(target);
 }
 
+/// A kernel stack. With MMU it is mapped in its slot of the kernel stack region,
+/// above an unmapped guard which catches overflows.
 pub struct KernelStack(usize);
 const STACK_SIZE: usize = 0x8000;
 
+/// The reason why a kernel stack can't be created
+#[derive(Debug)]
+pub enum KernelStackError {
+    /// All the slots of the kernel stack region are used
+    NoSlot,
+    /// The frames are exhausted
+    NoMemory,
+}
+
+#[cfg(not(feature = "no_mmu"))]
+lazy_static! {
+    /// Free slots of the kernel stack region
+    static ref KERNEL_STACK_SLOTS: SpinNoIrqLock<bit_allocator::BitAlloc256> = {
+        let mut slots = bit_allocator::BitAlloc256::default();
+        slots.insert(0..KERNEL_STACK_NUM);
+        SpinNoIrqLock::new(slots)
+    };
+}
+
+#[cfg(not(feature = "no_mmu"))]
+impl KernelStack {
+    pub fn new() -> Result<Self, KernelStackError> {
+        let slot = KERNEL_STACK_SLOTS.lock().alloc().ok_or(KernelStackError::NoSlot)?;
+        let bottom = KERNEL_STACK_OFFSET + (slot + 1) * KERNEL_STACK_SLOT_SIZE - STACK_SIZE;
+        // allocate all the frames before mapping, so that nothing is left on failure
+        let mut frames = [0usize; STACK_SIZE / PAGE_SIZE];
+        for i in 0..frames.len() {
+            match alloc_frame() {
+                Some(frame) => frames[i] = frame,
+                None => {
+                    for &frame in frames[..i].iter() {
+                        dealloc_frame(frame);
+                    }
+                    KERNEL_STACK_SLOTS.lock().dealloc(slot);
+                    return Err(KernelStackError::NoMemory);
+                }
+            }
+        }
+        let mut table = active_table();
+        for (addr, &frame) in (bottom..bottom + STACK_SIZE).step_by(PAGE_SIZE).zip(frames.iter()) {
+            table.map(addr, frame);
+        }
+        Ok(KernelStack(bottom))
+    }
+    pub fn top(&self) -> usize {
+        self.0 + STACK_SIZE
+    }
+}
+
+#[cfg(not(feature = "no_mmu"))]
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let mut table = active_table();
+        for addr in (self.0..self.0 + STACK_SIZE).step_by(PAGE_SIZE) {
+            let frame = table.get_entry(addr).expect("kernel stack not mapped").target();
+            table.unmap(addr);
+            dealloc_frame(frame);
+        }
+        KERNEL_STACK_SLOTS.lock().dealloc((self.0 - KERNEL_STACK_OFFSET) / KERNEL_STACK_SLOT_SIZE);
+    }
+}
+
+#[cfg(feature = "no_mmu")]
 impl KernelStack {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self, KernelStackError> {
         use alloc::alloc::{alloc, Layout};
         let bottom = unsafe{ alloc(Layout::from_size_align(STACK_SIZE, STACK_SIZE).unwrap()) } as usize;
-        KernelStack(bottom)
+        if bottom == 0 {
+            return Err(KernelStackError::NoMemory);
+        }
+        Ok(KernelStack(bottom))
     }
     pub fn top(&self) -> usize {
         self.0 + STACK_SIZE
     }
 }
 
+#[cfg(feature = "no_mmu")]
 impl Drop for KernelStack {
     fn drop(&mut self) {
         use alloc::alloc::{dealloc, Layout};
@@ -90,13 +161,68 @@ impl Drop for KernelStack {
     }
 }
 
+/// Whether `addr` is in the guard below a kernel stack
+pub fn is_kernel_stack_guard(addr: usize) -> bool {
+    addr >= KERNEL_STACK_OFFSET && addr - KERNEL_STACK_OFFSET < KERNEL_STACK_NUM * KERNEL_STACK_SLOT_SIZE
+        && (addr - KERNEL_STACK_OFFSET) % KERNEL_STACK_SLOT_SIZE < KERNEL_STACK_SLOT_SIZE - STACK_SIZE
+}
+
 
 /// Handle page fault at `addr`.
 /// Return true to continue, false to halt.
 #[cfg(not(feature = "no_mmu"))]
 pub fn page_fault_handler(addr: usize) -> bool {
     info!("start handling swap in/out page fault, badva={:x}", addr);
-    process().memory_set.page_fault_handler(addr)
+    // a write to a page shared by fork
+    if active_table().page_fault_handler(addr, || alloc_frame().expect("failed to allocate frame")) {
+        return true;
+    }
+    let memory_set_lock = process().memory_set.clone();
+    // the data of a page is loaded without the memory set locked, since it may be read from a file
+    let (handler_id, handler) = {
+        let memory_set = memory_set_lock.lock();
+        let handler = memory_set.find_area(addr).map(|area| area.get_handler().box_clone());
+        (area_handler_id(&memory_set, addr), handler)
+    };
+    let data = match handler.map_or(Ok(None), |handler| handler.load_page(addr)) {
+        Ok(data) => data,
+        Err(()) => {
+            warn!("failed to load the page at {:#x}", addr);
+            return false;
+        }
+    };
+    let mut memory_set = memory_set_lock.lock();
+    // the area is changed meanwhile, so load the page again
+    if area_handler_id(&memory_set, addr) != handler_id {
+        drop(memory_set);
+        return page_fault_handler(addr);
+    }
+    let pt = memory_set.get_page_table_mut() as *mut InactivePageTable0;
+    // a page swapped out
+    if crate::swap::swap_in(pt, addr) {
+        return true;
+    }
+    let handled = match data {
+        Some(ref data) => memory_set.page_fault_handler_with(addr, data),
+        None => memory_set.page_fault_handler(addr),
+    };
+    if handled {
+        // the user page brought into memory can be swapped out later
+        crate::swap::set_swappable(&memory_set_lock, pt, addr);
+        return true;
+    }
+    use crate::consts::{USER_STACK_OFFSET, USER_STACK_GUARD_SIZE};
+    if addr >= USER_STACK_OFFSET && addr < USER_STACK_OFFSET + USER_STACK_GUARD_SIZE {
+        warn!("user stack overflow at {:#x}", addr);
+    }
+    false
+}
+
+/// The identity of the handler of the area containing `addr`, or 0 if there is none
+#[cfg(not(feature = "no_mmu"))]
+fn area_handler_id(memory_set: &MemorySet, addr: usize) -> usize {
+    memory_set.find_area(addr)
+        .map_or(0, |area| area.get_handler() as *const MemoryHandler as *const u8 as usize)
 }
 
 pub fn init_heap() {
@@ -1,21 +1,59 @@
 use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::mem::size_of;
 
 use log::*;
-use simple_filesystem::file::File;
+use bitflags::bitflags;
 use spin::Mutex;
 use rcore_process::Context;
-use xmas_elf::{ElfFile, header, program::{Flags, Type}};
+use xmas_elf::{ElfFile, header, program::{Flags, ProgramHeader, Type}};
+use simple_filesystem::INode;
 
 use crate::arch::interrupt::{Context as ArchContext, TrapFrame};
-use crate::memory::{ByFrame, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet};
+use crate::memory::{ByFrame, Delay, File, GlobalFrameAlloc, KernelStack, KernelStackError, MemoryAttr, MemorySet};
+use crate::fs::{FileHandle, INodeForMap};
+use crate::sync::SpinNoIrqLock;
+use crate::signal::{SigAction, NSIG};
+use rcore_memory::PAGE_SIZE;
 
 // TODO: avoid pub
+/// A thread. The memory set, the file table, the working directory and the signal actions
+/// may be shared with other threads created by `clone`.
 pub struct Process {
     pub arch: ArchContext,
-    pub memory_set: MemorySet,
+    pub memory_set: Arc<SpinNoIrqLock<MemorySet>>,
+    /// The heap in the memory set, shared along with it
+    pub heap: Arc<SpinNoIrqLock<Heap>>,
     pub kstack: KernelStack,
-    pub files: BTreeMap<usize, Arc<Mutex<File>>>,
-    pub cwd: String,
+    pub files: Arc<SpinNoIrqLock<BTreeMap<usize, FileDesc>>>,
+    pub cwd: Arc<SpinNoIrqLock<Cwd>>,
+    pub sig_actions: Arc<SpinNoIrqLock<[SigAction; NSIG]>>,
+    /// Blocked signals, bit `n` for signal `n`
+    pub sig_mask: usize,
+}
+
+/// An entry in the file descriptor table.
+/// The open file is shared by `dup` and `fork`, including its offset.
+#[derive(Clone)]
+pub struct FileDesc {
+    pub file: Arc<Mutex<FileHandle>>,
+    /// Close the file on `exec`
+    pub cloexec: bool,
+}
+
+/// The working directory
+#[derive(Clone)]
+pub struct Cwd {
+    /// Relative paths are looked up from it, even after it is renamed
+    pub inode: Arc<INode>,
+    /// The absolute path it was entered by, for `getcwd`
+    pub path: String,
+}
+
+impl Cwd {
+    /// The root directory
+    pub fn root() -> Self {
+        Cwd { inode: crate::fs::ROOT_INODE.clone(), path: String::from("/") }
+    }
 }
 
 impl Context for Process {
@@ -26,113 +64,286 @@ impl Context for Process {
     }
 }
 
+bitflags! {
+    /// Resources shared between the caller and the new thread of `clone`
+    pub struct CloneFlags: usize {
+        /// Share the memory set
+        const VM = 0x100;
+        /// Share the working directory
+        const FS = 0x200;
+        /// Share the file descriptor table
+        const FILES = 0x400;
+        /// Share the signal actions
+        const SIGHAND = 0x800;
+        /// Put the new thread in the caller's thread group
+        const THREAD = 0x10000;
+    }
+}
+
+/// The heap area of a memory set, which is resized by `brk`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Heap {
+    /// The page aligned start, or 0 if there is no heap
+    pub start: usize,
+    /// The end, which may be not page aligned. The pages up to it are mapped.
+    pub end: usize,
+}
+
+impl Heap {
+    /// Called when the pages in `[start, end)` are unmapped.
+    /// If the top of the heap is unmapped, the heap ends at `start`.
+    pub fn unmap(&mut self, start: usize, end: usize) {
+        let top = (self.end + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        if self.start != 0 && start < top && top <= end {
+            self.end = start.max(self.start);
+        }
+    }
+}
+
 impl Process {
     pub unsafe fn new_init() -> Box<Context> {
         Box::new(Process {
             arch: ArchContext::null(),
-            memory_set: MemorySet::new(),
-            kstack: KernelStack::new(),
-            files: BTreeMap::default(),
-            cwd: String::new(),
+            memory_set: Arc::new(SpinNoIrqLock::new(MemorySet::new())),
+            heap: Arc::new(SpinNoIrqLock::new(Heap::default())),
+            kstack: KernelStack::new().expect("failed to create kernel stack"),
+            files: Arc::new(SpinNoIrqLock::new(BTreeMap::default())),
+            cwd: Arc::new(SpinNoIrqLock::new(Cwd::root())),
+            sig_actions: Arc::new(SpinNoIrqLock::new([SigAction::default(); NSIG])),
+            sig_mask: 0,
         })
     }
 
     pub fn new_kernel(entry: extern fn(usize) -> !, arg: usize) -> Box<Context> {
         let memory_set = MemorySet::new();
-        let kstack = KernelStack::new();
+        let kstack = KernelStack::new().expect("failed to create kernel stack");
         Box::new(Process {
             arch: unsafe { ArchContext::new_kernel_thread(entry, arg, kstack.top(), memory_set.token()) },
-            memory_set,
+            memory_set: Arc::new(SpinNoIrqLock::new(memory_set)),
+            heap: Arc::new(SpinNoIrqLock::new(Heap::default())),
             kstack,
-            files: BTreeMap::default(),
-            cwd: String::new(),
+            files: Arc::new(SpinNoIrqLock::new(BTreeMap::default())),
+            cwd: Arc::new(SpinNoIrqLock::new(Cwd::root())),
+            sig_actions: Arc::new(SpinNoIrqLock::new([SigAction::default(); NSIG])),
+            sig_mask: 0,
         })
     }
 
-    /// Make a new user thread from ELF data
-    pub fn new_user<'a, Iter>(data: &[u8], args: Iter) -> Box<Process>
-        where Iter: Iterator<Item=&'a str>
+    /// Make a new user thread from the ELF file `inode`.
+    /// Its segments are loaded from the file on demand.
+    /// A position-independent executable is loaded at `USER_PIE_OFFSET`.
+    /// If the ELF has an interpreter, it is loaded at `USER_INTERP_OFFSET` and runs first.
+    /// `envs` are the environment variables in the form of "KEY=VALUE".
+    /// Return an error if the ELF is invalid or can't run on this machine.
+    pub fn new_user<'a, Iter, EnvIter>(inode: &Arc<INode>, args: Iter, envs: EnvIter) -> Result<Box<Process>, &'static str>
+        where Iter: Iterator<Item=&'a str>, EnvIter: Iterator<Item=&'a str>
     {
+        use crate::consts::{USER_STACK_OFFSET, USER_STACK_SIZE, USER_STACK_GUARD_SIZE, USER32_STACK_OFFSET, USER_PIE_OFFSET, USER_INTERP_OFFSET};
+
         // Parse elf
-        let elf = ElfFile::new(data).expect("failed to read elf");
-        let is32 = match elf.header.pt2 {
-            header::HeaderPt2::Header32(_) => true,
-            header::HeaderPt2::Header64(_) => false,
+        let (data, size) = read_elf_header(inode)?;
+        let elf = ElfFile::new(&data)?;
+        let is32 = is_elf32(&elf);
+        let base = load_base(&elf, USER_PIE_OFFSET);
+
+        // Parse the interpreter. The program must not overlap it.
+        let interp_inode = match interpreter_path(&elf)? {
+            Some(path) => Some(find_interpreter(path)?),
+            None => None,
+        };
+        let interp_data = match interp_inode {
+            Some(ref inode) => Some(read_elf_header(inode)?),
+            None => None,
         };
+        let interp = match interp_data {
+            Some((ref data, size)) => Some((ElfFile::new(data)?, size)),
+            None => None,
+        };
+        #[cfg(feature = "no_mmu")]
+        {
+            if interp.is_some() {
+                return Err("ELF interpreter is not supported without MMU");
+            }
+        }
+        if let Some((ref interp, size)) = interp {
+            if interp.header.pt2.type_().as_type() != header::Type::SharedObject {
+                return Err("ELF interpreter is not a shared object");
+            }
+            if is_elf32(interp) != is32 {
+                return Err("ELF interpreter is for another class");
+            }
+            if interpreter_path(interp)?.is_some() {
+                return Err("ELF interpreter has an interpreter");
+            }
+            check_elf(interp, size, USER_INTERP_OFFSET, USER_STACK_OFFSET)?;
+        }
+        let limit = if interp.is_some() { USER_INTERP_OFFSET } else { USER_STACK_OFFSET };
+        check_elf(&elf, size, base, limit)?;
 
-        match elf.header.pt2.type_().as_type() {
-            header::Type::Executable => {
-//                #[cfg(feature = "no_mmu")]
-//                panic!("ELF is not shared object");
-            },
-            header::Type::SharedObject => {},
-            _ => panic!("ELF is not executable or shared object"),
+        // The args and envs must fit in the user stack
+        let args: Vec<&str> = args.collect();
+        let envs: Vec<&str> = envs.collect();
+        let args_size = args.iter().chain(envs.iter())
+            .map(|arg| arg.len() + 1 + size_of::<usize>()).sum::<usize>()
+            + size_of::<usize>() * 3;
+        if args_size > USER_STACK_SIZE / 2 {
+            return Err("arguments are too long");
         }
 
         // Make page table
-        let (mut memory_set, entry_addr) = memory_set_from(&elf);
+        // A static PIE is relocated here, otherwise the interpreter relocates the program.
+        // The relocated segments are loaded at once, since the kernel can't handle their page faults.
+        let relocate_here = elf.header.pt2.type_().as_type() == header::Type::SharedObject && interp.is_none();
+        let (mut memory_set, base) = memory_set_from(&elf, inode, base, relocate_here)?;
+        if relocate_here {
+            let mut result = Ok(());
+            unsafe { memory_set.with(|| result = relocate(&elf, base, is32)); }
+            result?;
+        }
+        protect_segments(&mut memory_set, &elf, base);
+        #[allow(unused_mut)]
+        let mut entry = base.wrapping_add(elf.header.pt2.entry_point() as usize);
+        #[cfg(feature = "board_k210")]
+        { entry += 0x40000000; }
+
+        #[cfg(not(feature = "no_mmu"))]
+        let interp_base = match interp {
+            Some((ref interp, _)) => {
+                map_segments(&mut memory_set, interp, interp_inode.as_ref().unwrap(), USER_INTERP_OFFSET, false)?;
+                protect_segments(&mut memory_set, interp, USER_INTERP_OFFSET);
+                USER_INTERP_OFFSET
+            }
+            None => 0,
+        };
+        #[cfg(feature = "no_mmu")]
+        let interp_base = 0;
+        // The heap starts after the last segment of the program
+        #[cfg(not(feature = "no_mmu"))]
+        let heap_start = elf.program_iter()
+            .filter(|ph| ph.get_type() == Ok(Type::Load))
+            .map(|ph| base + (ph.virtual_addr() + ph.mem_size()) as usize + PAGE_SIZE - 1)
+            .max().unwrap() / PAGE_SIZE * PAGE_SIZE;
+        #[cfg(feature = "no_mmu")]
+        let heap_start = 0;
+        let entry_addr = match interp {
+            Some((ref interp, _)) => interp_base + interp.header.pt2.entry_point() as usize,
+            None => entry,
+        };
+
+        // The auxiliary vector tells the interpreter where the program is
+        let mut auxv = vec![
+            (AT_PHENT, elf.header.pt2.ph_entry_size() as usize),
+            (AT_PHNUM, elf.header.pt2.ph_count() as usize),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_BASE, interp_base),
+            (AT_ENTRY, entry),
+        ];
+        if let Some(phdr) = phdr_addr(&elf) {
+            auxv.push((AT_PHDR, base.wrapping_add(phdr)));
+        }
 
-        // User stack
-        use crate::consts::{USER_STACK_OFFSET, USER_STACK_SIZE, USER32_STACK_OFFSET};
+        // User stack. It's above a guard gap and grows on demand up to USER_STACK_SIZE.
         #[cfg(not(feature = "no_mmu"))]
         let mut ustack_top = {
-            let (ustack_buttom, ustack_top) = match is32 {
-                true => (USER32_STACK_OFFSET, USER32_STACK_OFFSET + USER_STACK_SIZE),
-                false => (USER_STACK_OFFSET, USER_STACK_OFFSET + USER_STACK_SIZE),
+            let stack_offset = match is32 {
+                true => USER32_STACK_OFFSET,
+                false => USER_STACK_OFFSET,
             };
-            memory_set.push(ustack_buttom, ustack_top,  ByFrame::new(MemoryAttr::default().user(), GlobalFrameAlloc), "user_stack");
+            let ustack_buttom = stack_offset + USER_STACK_GUARD_SIZE;
+            let ustack_top = ustack_buttom + USER_STACK_SIZE;
+            memory_set.push(ustack_buttom, ustack_top, MemoryAttr::default().user(), Delay::new(GlobalFrameAlloc), "user_stack");
+            // The kernel can't handle page faults of an inactive memory set,
+            // so fault in the pages for the args before pushing them
+            let init_size = args_size + (auxv.len() + 1) * 2 * size_of::<usize>() + 0x10;
+            for addr in ((ustack_top - init_size) & !(PAGE_SIZE - 1)..ustack_top).step_by(PAGE_SIZE) {
+                memory_set.page_fault_handler(addr);
+            }
             ustack_top
         };
         #[cfg(feature = "no_mmu")]
         let mut ustack_top = memory_set.push(USER_STACK_SIZE).as_ptr() as usize + USER_STACK_SIZE;
 
         unsafe {
-            memory_set.with(|| { ustack_top = push_args_at_stack(args, ustack_top) });
+            memory_set.with(|| { ustack_top = push_args_at_stack(&args, &envs, &auxv, ustack_top) });
         }
 
         trace!("{:#x?}", memory_set);
 
-        let kstack = KernelStack::new();
+        let kstack = KernelStack::new().map_err(|_| "failed to create kernel stack")?;
 
-        Box::new(Process {
+        Ok(Box::new(Process {
             arch: unsafe {
                 ArchContext::new_user_thread(
                     entry_addr, ustack_top, kstack.top(), is32, memory_set.token())
             },
-            memory_set,
+            memory_set: Arc::new(SpinNoIrqLock::new(memory_set)),
+            heap: Arc::new(SpinNoIrqLock::new(Heap { start: heap_start, end: heap_start })),
             kstack,
-            files: BTreeMap::default(),
-            cwd: String::new(),
-        })
+            files: Arc::new(SpinNoIrqLock::new(BTreeMap::default())),
+            cwd: Arc::new(SpinNoIrqLock::new(Cwd::root())),
+            sig_actions: Arc::new(SpinNoIrqLock::new([SigAction::default(); NSIG])),
+            sig_mask: 0,
+        }))
     }
 
     /// Fork
-    pub fn fork(&self, tf: &TrapFrame) -> Box<Context> {
-        info!("COME into fork!");
-        // Clone memory set, make a new page table
-        let memory_set = self.memory_set.clone();
-        info!("finish mmset clone in fork!");
-
-        // MMU:   copy data to the new space
-        // NoMMU: coping data has been done in `memory_set.clone()`
-        #[cfg(not(feature = "no_mmu"))]
-        for area in memory_set.iter() {
-            let data = Vec::<u8>::from(unsafe { area.as_slice() });
-            unsafe { memory_set.with(|| {
-                area.as_slice_mut().copy_from_slice(data.as_slice())
-            }) }
-        }
+    pub fn fork(&mut self, tf: &TrapFrame) -> Result<Box<Context>, KernelStackError> {
+        self.clone(CloneFlags::empty(), 0, tf)
+    }
+
+    /// Create a new thread sharing the resources selected by `flags`.
+    /// The new thread starts at the user stack `ustack_top` if it is not 0.
+    /// Return an error if its kernel stack can't be created.
+    pub fn clone(&mut self, flags: CloneFlags, ustack_top: usize, tf: &TrapFrame) -> Result<Box<Context>, KernelStackError> {
+        info!("COME into clone! flags: {:?}", flags);
+        let kstack = KernelStack::new()?;
+        let memory_set = match flags.contains(CloneFlags::VM) {
+            true => self.memory_set.clone(),
+            false => {
+                // MMU:   share the frames with the new page table, copy them on write
+                // NoMMU: copy data to the new space
+                #[cfg(not(feature = "no_mmu"))]
+                let memory_set = {
+                    let mut memory_set = self.memory_set.lock();
+                    crate::swap::swap_in_all(&mut memory_set);
+                    memory_set.fork()
+                };
+                #[cfg(feature = "no_mmu")]
+                let memory_set = self.memory_set.lock().clone();
+                info!("finish mmset fork!");
+                Arc::new(SpinNoIrqLock::new(memory_set))
+            }
+        };
+        let heap = match flags.contains(CloneFlags::VM) {
+            true => self.heap.clone(),
+            false => Arc::new(SpinNoIrqLock::new(*self.heap.lock())),
+        };
+        let files = match flags.contains(CloneFlags::FILES) {
+            true => self.files.clone(),
+            false => Arc::new(SpinNoIrqLock::new(self.files.lock().clone())),
+        };
+        let cwd = match flags.contains(CloneFlags::FS) {
+            true => self.cwd.clone(),
+            false => Arc::new(SpinNoIrqLock::new(self.cwd.lock().clone())),
+        };
 
-        info!("temporary copy data!");
-        let kstack = KernelStack::new();
+        let sig_actions = match flags.contains(CloneFlags::SIGHAND) {
+            true => self.sig_actions.clone(),
+            false => Arc::new(SpinNoIrqLock::new(*self.sig_actions.lock())),
+        };
 
-        Box::new(Process {
-            arch: unsafe { ArchContext::new_fork(tf, kstack.top(), memory_set.token()) },
+        let token = memory_set.lock().token();
+
+        Ok(Box::new(Process {
+            arch: unsafe { ArchContext::new_clone(tf, ustack_top, kstack.top(), token) },
             memory_set,
+            heap,
             kstack,
-            files: BTreeMap::default(),
-            cwd: String::new(),
-        })
+            files,
+            cwd,
+            sig_actions,
+            sig_mask: self.sig_mask,
+        }))
     }
 }
 
@@ -146,44 +357,154 @@ unsafe fn push_slice<T: Copy>(mut sp: usize, vs: &[T]) -> usize {
     sp
 }
 
-unsafe fn push_args_at_stack<'a, Iter>(args: Iter, stack_top: usize) -> usize
-    where Iter: Iterator<Item=&'a str>
-{
+/// Push the args, the envs and the auxiliary vector at the stack as the psABI describes.
+/// Return the new sp, from which up there are argc, argv, NULL, envp, NULL, and the auxv ended by AT_NULL.
+/// The strings are above them.
+unsafe fn push_args_at_stack(args: &[&str], envs: &[&str], auxv: &[(usize, usize)], stack_top: usize) -> usize {
     let mut sp = stack_top;
-    let mut argv = Vec::new();
-    for arg in args {
+    let mut push_str = |s: &str| {
         sp = push_slice(sp, &[0u8]);
-        sp = push_slice(sp, arg.as_bytes());
-        argv.push(sp);
+        sp = push_slice(sp, s.as_bytes());
+        sp
+    };
+    let argv: Vec<usize> = args.iter().map(|arg| push_str(arg)).collect();
+    let envp: Vec<usize> = envs.iter().map(|env| push_str(env)).collect();
+    let mut stack = vec![argv.len()];
+    stack.extend(argv);
+    stack.push(0);
+    stack.extend(envp);
+    stack.push(0);
+    for &(key, value) in auxv {
+        stack.push(key);
+        stack.push(value);
     }
-    sp = push_slice(sp, argv.as_slice());
-    sp = push_slice(sp, &[argv.len()]);
-    sp
+    stack.push(AT_NULL);
+    stack.push(0);
+    // sp is 16-byte aligned at the entry
+    sp = (sp - stack.len() * size_of::<usize>()) & !0xf;
+    push_slice(sp + stack.len() * size_of::<usize>(), stack.as_slice())
 }
 
+/// Auxiliary vector keys
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_ENTRY: usize = 9;
 
-/// Generate a MemorySet according to the ELF file.
-/// Also return the real entry point address.
-fn memory_set_from(elf: &ElfFile<'_>) -> (MemorySet, usize) {
-    debug!("come in to memory_set_from");
-    let mut ms = MemorySet::new();
-    let mut entry = elf.header.pt2.entry_point() as usize;
+fn is_elf32(elf: &ElfFile<'_>) -> bool {
+    match elf.header.pt2 {
+        header::HeaderPt2::Header32(_) => true,
+        header::HeaderPt2::Header64(_) => false,
+    }
+}
 
-    // [NoMMU] Get total memory size and alloc space
-    let va_begin = elf.program_iter()
-        .filter(|ph| ph.get_type() == Ok(Type::Load))
-        .map(|ph| ph.virtual_addr()).min().unwrap() as usize;
-    let va_end = elf.program_iter()
+/// Where the ELF should be loaded: `pie_base` if it is position-independent, otherwise 0.
+fn load_base(elf: &ElfFile<'_>, pie_base: usize) -> usize {
+    match elf.header.pt2.type_().as_type() {
+        header::Type::SharedObject => pie_base,
+        _ => 0,
+    }
+}
+
+/// The path of the ELF interpreter, if there is one
+fn interpreter_path<'a>(elf: &ElfFile<'a>) -> Result<Option<&'a str>, &'static str> {
+    let ph = match elf.program_iter().find(|ph| ph.get_type() == Ok(Type::Interp)) {
+        Some(ph) => ph,
+        None => return Ok(None),
+    };
+    let offset = ph.offset() as usize;
+    let data = offset.checked_add(ph.file_size() as usize)
+        .and_then(|end| elf.input.get(offset..end))
+        .ok_or("ELF interpreter path is out of the file")?;
+    // the path is terminated by '\0'
+    let path = data.split(|&c| c == 0).next().unwrap();
+    core::str::from_utf8(path).map(Some).map_err(|_| "ELF interpreter path is invalid")
+}
+
+/// Find the ELF interpreter in the file system
+fn find_interpreter(path: &str) -> Result<Arc<INode>, &'static str> {
+    // INode::lookup takes a path relative to the root
+    crate::fs::ROOT_INODE.lookup(path.trim_start_matches('/'))
+        .map_err(|_| "ELF interpreter is not found")
+}
+
+/// Read the beginning of the ELF file with the headers and the interpreter path,
+/// which are used to check and load it. Also return the file size.
+fn read_elf_header(inode: &Arc<INode>) -> Result<(Vec<u8>, usize), &'static str> {
+    let size = inode.info().map_err(|_| "failed to read the ELF")?.size;
+    let data = read_file(inode, size.min(PAGE_SIZE))?;
+    // the program headers may be out of the first page
+    let ph_end = {
+        let pt2 = &ElfFile::new(&data)?.header.pt2;
+        (pt2.ph_entry_size() as u64 * pt2.ph_count() as u64).saturating_add(pt2.ph_offset())
+    };
+    if ph_end > size as u64 {
+        return Err("program headers are out of the file");
+    }
+    let data = match ph_end as usize > data.len() {
+        true => read_file(inode, ph_end as usize)?,
+        false => data,
+    };
+    // so may be the interpreter path
+    let interp_end = ElfFile::new(&data)?.program_iter()
+        .filter(|ph| ph.get_type() == Ok(Type::Interp))
+        .map(|ph| ph.offset().saturating_add(ph.file_size()))
+        .max().unwrap_or(0);
+    if interp_end > size as u64 {
+        return Err("ELF interpreter path is out of the file");
+    }
+    let data = match interp_end as usize > data.len() {
+        true => read_file(inode, interp_end as usize)?,
+        false => data,
+    };
+    Ok((data, size))
+}
+
+/// Read the first `len` bytes of the file
+fn read_file(inode: &Arc<INode>, len: usize) -> Result<Vec<u8>, &'static str> {
+    let mut buf = vec![0u8; len];
+    match inode.read_at(0, &mut buf) {
+        Ok(read) if read == len => Ok(buf),
+        _ => Err("failed to read the ELF"),
+    }
+}
+
+/// The address of the program headers before the ELF is moved to its load base
+fn phdr_addr(elf: &ElfFile<'_>) -> Option<usize> {
+    if let Some(ph) = elf.program_iter().find(|ph| ph.get_type() == Ok(Type::Phdr)) {
+        return Some(ph.virtual_addr() as usize);
+    }
+    let phoff = elf.header.pt2.ph_offset();
+    elf.program_iter()
         .filter(|ph| ph.get_type() == Ok(Type::Load))
-        .map(|ph| ph.virtual_addr() + ph.mem_size()).max().unwrap() as usize;
-    let va_size = va_end - va_begin;
-    #[cfg(feature = "no_mmu")]
-    let target = ms.push(va_size);
-    #[cfg(feature = "no_mmu")]
-    { entry = entry - va_begin + target.as_ptr() as usize; }
-    #[cfg(feature = "board_k210")]
-    { entry += 0x40000000; }
+        .find(|ph| ph.offset() <= phoff && phoff < ph.offset() + ph.file_size())
+        .map(|ph| (phoff - ph.offset() + ph.virtual_addr()) as usize)
+}
 
+/// Check that the ELF is executable on this machine,
+/// and its segments in the file of `elf_size` bytes can be loaded at `base` below `limit`.
+#[cfg_attr(feature = "no_mmu", allow(unused_variables))]
+fn check_elf(elf: &ElfFile<'_>, elf_size: usize, base: usize, limit: usize) -> Result<(), &'static str> {
+    match elf.header.pt2.type_().as_type() {
+        header::Type::Executable | header::Type::SharedObject => {}
+        _ => return Err("ELF is not executable or shared object"),
+    }
+    if !is_machine_supported(elf.header.pt2.machine().as_machine()) {
+        return Err("ELF is for another machine");
+    }
+    if let header::HeaderPt2::Header64(_) = elf.header.pt2 {
+        if size_of::<usize>() < 8 {
+            return Err("64-bit ELF is not supported");
+        }
+    }
+
+    let entry = elf.header.pt2.entry_point() as usize;
+    let mut entry_found = false;
+    // page ranges of the segments
+    let mut pages: Vec<(usize, usize)> = Vec::new();
     for ph in elf.program_iter() {
         if ph.get_type() != Ok(Type::Load) {
             continue;
@@ -192,36 +513,310 @@ fn memory_set_from(elf: &ElfFile<'_>) -> (MemorySet, usize) {
         let offset = ph.offset() as usize;
         let file_size = ph.file_size() as usize;
         let mem_size = ph.mem_size() as usize;
+        let align = ph.align() as usize;
 
+        if file_size > mem_size {
+            return Err("segment file size is larger than memory size");
+        }
+        if offset.checked_add(file_size).map_or(true, |end| end > elf_size) {
+            return Err("segment is out of the file");
+        }
+        let end = virt_addr.checked_add(mem_size).ok_or("segment is out of the user space")?;
+        #[cfg(not(any(feature = "no_mmu", target_arch = "aarch64")))]
+        {
+            if base.checked_add(end).map_or(true, |end| end > limit) {
+                return Err("segment is out of the user space");
+            }
+        }
         #[cfg(target_arch = "aarch64")]
-        assert_eq!((virt_addr >> 48), 0xffff, "Segment Fault");
+        {
+            if (base + virt_addr) >> 48 != 0xffff {
+                return Err("segment is out of the user space");
+            }
+        }
+        if align > 1 && (!align.is_power_of_two() || virt_addr % align != offset % align) {
+            return Err("segment is misaligned");
+        }
+        if ph.flags().is_write() && ph.flags().is_execute() {
+            return Err("segment is both writable and executable");
+        }
+        if mem_size == 0 {
+            continue;
+        }
+        let range = (virt_addr / PAGE_SIZE, (end - 1) / PAGE_SIZE + 1);
+        if pages.iter().any(|&(start, end)| start < range.1 && range.0 < end) {
+            return Err("segments overlap");
+        }
+        pages.push(range);
+        entry_found |= virt_addr <= entry && entry < end;
+    }
+    if pages.is_empty() {
+        return Err("ELF has no loadable segment");
+    }
+    if !entry_found {
+        return Err("entry point is not in any segment");
+    }
+    Ok(())
+}
 
-        // Get target slice
-        #[cfg(feature = "no_mmu")]
-        let target = &mut target[virt_addr - va_begin..virt_addr - va_begin + mem_size];
-        #[cfg(feature = "no_mmu")]
-        info!("area @ {:?}, size = {:#x}", target.as_ptr(), mem_size);
-        #[cfg(not(feature = "no_mmu"))]
-        let target = {
-            ms.push(virt_addr, virt_addr + mem_size, ByFrame::new(memory_attr_from(ph.flags()), GlobalFrameAlloc), "");
-            unsafe { ::core::slice::from_raw_parts_mut(virt_addr as *mut u8, mem_size) }
-        };
-        // Copy data
+fn is_machine_supported(machine: header::Machine) -> bool {
+    match machine {
+        #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+        header::Machine::RISC_V => true,
+        #[cfg(target_arch = "x86_64")]
+        header::Machine::X86_64 | header::Machine::X86 => true,
+        #[cfg(target_arch = "aarch64")]
+        header::Machine::AArch64 => true,
+        _ => false,
+    }
+}
+
+/// Generate a MemorySet with the segments of the ELF file `inode` loaded at `base`.
+/// If `eager`, they are loaded at once and writable until `protect_segments`.
+/// Also return the real load base, which is chosen by the MemorySet without MMU.
+#[cfg_attr(feature = "no_mmu", allow(unused_variables))]
+fn memory_set_from(elf: &ElfFile<'_>, inode: &Arc<INode>, base: usize, eager: bool) -> Result<(MemorySet, usize), &'static str> {
+    debug!("come in to memory_set_from");
+    let mut ms = MemorySet::new();
+
+    #[cfg(not(feature = "no_mmu"))]
+    map_segments(&mut ms, elf, inode, base, eager)?;
+
+    // [NoMMU] Get total memory size and alloc space
+    // there is at least one segment, checked by `check_elf`
+    #[cfg(feature = "no_mmu")]
+    let base = {
+        let va_begin = elf.program_iter()
+            .filter(|ph| ph.get_type() == Ok(Type::Load))
+            .map(|ph| ph.virtual_addr()).min().unwrap_or(0) as usize;
+        let va_end = elf.program_iter()
+            .filter(|ph| ph.get_type() == Ok(Type::Load))
+            .map(|ph| ph.virtual_addr() + ph.mem_size()).max().unwrap_or(0) as usize;
+        let target = ms.push(va_end - va_begin);
+        info!("area @ {:?}, size = {:#x}", target.as_ptr(), target.len());
+        for ph in elf.program_iter() {
+            if ph.get_type() != Ok(Type::Load) || ph.mem_size() == 0 {
+                continue;
+            }
+            let start = ph.virtual_addr() as usize - va_begin;
+            copy_segment(&mut target[start..start + ph.mem_size() as usize], inode, ph)?;
+        }
+        (target.as_ptr() as usize).wrapping_sub(va_begin)
+    };
+    Ok((ms, base))
+}
+
+/// Map the segments of the ELF file `inode` at `base`.
+/// If `eager`, map them writable and copy the data into them,
+/// otherwise the pages are read from the file on the first access.
+#[cfg(not(feature = "no_mmu"))]
+fn map_segments(ms: &mut MemorySet, elf: &ElfFile<'_>, inode: &Arc<INode>, base: usize, eager: bool) -> Result<(), &'static str> {
+    for ph in elf.program_iter() {
+        if ph.get_type() != Ok(Type::Load) || ph.mem_size() == 0 {
+            continue;
+        }
+        let virt_addr = base + ph.virtual_addr() as usize;
+        let mem_size = ph.mem_size() as usize;
+        if !eager {
+            let offset = ph.offset() as usize;
+            let handler = File::new(INodeForMap(inode.clone()), virt_addr,
+                                    offset, offset + ph.file_size() as usize, GlobalFrameAlloc);
+            ms.push(virt_addr, virt_addr + mem_size, memory_attr_from(ph.flags()), handler, "");
+            continue;
+        }
+        // The kernel can't write to a readonly page either,
+        // so map it writable until the data is copied
+        ms.push(virt_addr, virt_addr + mem_size, MemoryAttr::default().user(), ByFrame::new(GlobalFrameAlloc), "");
+        let mut result = Ok(());
         unsafe {
-            ms.with(|| {
-                if file_size != 0 {
-                    target[..file_size].copy_from_slice(&elf.input[offset..offset + file_size]);
-                }
-                target[file_size..].iter_mut().for_each(|x| *x = 0);
-            });
+            let target = ::core::slice::from_raw_parts_mut(virt_addr as *mut u8, mem_size);
+            ms.with(|| result = copy_segment(target, inode, ph));
+        }
+        result?;
+    }
+    Ok(())
+}
+
+/// Copy the data of the segment `ph` from the file to `target`, and clear the rest of it
+fn copy_segment(target: &mut [u8], inode: &Arc<INode>, ph: ProgramHeader<'_>) -> Result<(), &'static str> {
+    let file_size = ph.file_size() as usize;
+    let read = inode.read_at(ph.offset() as usize, &mut target[..file_size])
+        .map_err(|_| "failed to read ELF segment")?;
+    target[read..].iter_mut().for_each(|x| *x = 0);
+    Ok(())
+}
+
+/// Set the attributes of the segments of the ELF loaded at `base`
+#[cfg_attr(feature = "no_mmu", allow(unused_variables))]
+fn protect_segments(ms: &mut MemorySet, elf: &ElfFile<'_>, base: usize) {
+    #[cfg(not(feature = "no_mmu"))]
+    for ph in elf.program_iter() {
+        if ph.get_type() != Ok(Type::Load) || ph.mem_size() == 0 {
+            continue;
         }
+        let virt_addr = base + ph.virtual_addr() as usize;
+        ms.protect(virt_addr, virt_addr + ph.mem_size() as usize, memory_attr_from(ph.flags()))
+            .expect("failed to protect the segment");
     }
-    (ms, entry)
 }
 
+/// The attribute of a segment. The segment is not both writable and executable, checked by `check_elf`.
 fn memory_attr_from(elf_flags: Flags) -> MemoryAttr {
     let mut flags = MemoryAttr::default().user();
-    // TODO: handle readonly
+    if !elf_flags.is_write() { flags = flags.readonly(); }
     if elf_flags.is_execute() { flags = flags.execute(); }
     flags
 }
+
+/// Dynamic section tags
+const DT_NULL: usize = 0;
+const DT_SYMTAB: usize = 6;
+const DT_RELA: usize = 7;
+const DT_RELASZ: usize = 8;
+const DT_RELAENT: usize = 9;
+const DT_SYMENT: usize = 11;
+const DT_REL: usize = 17;
+const DT_RELSZ: usize = 18;
+const DT_RELENT: usize = 19;
+
+/// Relocation types which need no other object
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+mod reloc {
+    pub const NONE: usize = 0;
+    pub const RELATIVE: usize = 3;
+    /// R_RISCV_32, R_RISCV_64, R_RISCV_JUMP_SLOT
+    pub const SYMBOLIC: [usize; 3] = [1, 2, 5];
+}
+#[cfg(target_arch = "x86_64")]
+mod reloc {
+    pub const NONE: usize = 0;
+    pub const RELATIVE: usize = 8;
+    /// R_X86_64_64, R_X86_64_GLOB_DAT, R_X86_64_JUMP_SLOT
+    pub const SYMBOLIC: [usize; 3] = [1, 6, 7];
+}
+#[cfg(target_arch = "aarch64")]
+mod reloc {
+    pub const NONE: usize = 0;
+    pub const RELATIVE: usize = 1027;
+    /// R_AARCH64_ABS64, R_AARCH64_GLOB_DAT, R_AARCH64_JUMP_SLOT
+    pub const SYMBOLIC: [usize; 3] = [257, 1025, 1026];
+}
+
+/// Apply the dynamic relocations of the ELF loaded at `base`, in its memory set.
+/// The symbols must be defined in the ELF itself.
+fn relocate(elf: &ElfFile<'_>, base: usize, is32: bool) -> Result<(), &'static str> {
+    let word = if is32 { 4 } else { 8 };
+    let ph = match elf.program_iter().find(|ph| ph.get_type() == Ok(Type::Dynamic)) {
+        Some(ph) => ph,
+        None => return Ok(()),
+    };
+    let (addr, size) = (ph.virtual_addr() as usize, ph.mem_size() as usize);
+    check_loaded(elf, addr, size)?;
+    // the values of the tags we need, which are all small
+    let mut dynamic = [0usize; DT_RELENT + 1];
+    let start = base.wrapping_add(addr);
+    for entry in (start..start + size / (2 * word) * (2 * word)).step_by(2 * word) {
+        let tag = unsafe { read_word(entry, is32) };
+        if tag == DT_NULL {
+            break;
+        }
+        if tag < dynamic.len() {
+            dynamic[tag] = unsafe { read_word(entry + word, is32) };
+        }
+    }
+    let symtab = (dynamic[DT_SYMTAB], dynamic[DT_SYMENT]);
+    relocate_table(elf, base, is32, (dynamic[DT_RELA], dynamic[DT_RELASZ], dynamic[DT_RELAENT]), true, symtab)?;
+    relocate_table(elf, base, is32, (dynamic[DT_REL], dynamic[DT_RELSZ], dynamic[DT_RELENT]), false, symtab)
+}
+
+/// Apply a REL or RELA table given by its (address, size, entry size)
+fn relocate_table(elf: &ElfFile<'_>, base: usize, is32: bool, (addr, size, entry_size): (usize, usize, usize),
+                  rela: bool, symtab: (usize, usize)) -> Result<(), &'static str> {
+    if size == 0 {
+        return Ok(());
+    }
+    let word = if is32 { 4 } else { 8 };
+    let entry_size = match entry_size {
+        0 if rela => 3 * word,
+        0 => 2 * word,
+        _ => entry_size,
+    };
+    check_loaded(elf, addr, size)?;
+    let start = base.wrapping_add(addr);
+    for entry in (start..start + size / entry_size * entry_size).step_by(entry_size) {
+        let (offset, info) = unsafe { (read_word(entry, is32), read_word(entry + word, is32)) };
+        let (ty, sym) = match is32 {
+            true => (info & 0xff, info >> 8),
+            false => (info & 0xffff_ffff, (info as u64 >> 32) as usize),
+        };
+        if ty == reloc::NONE {
+            continue;
+        }
+        check_loaded(elf, offset, word)?;
+        let target = base.wrapping_add(offset);
+        let addend = match rela {
+            true => unsafe { read_word(entry + 2 * word, is32) },
+            false => unsafe { read_word(target, is32) },
+        };
+        let value = match ty {
+            reloc::RELATIVE => base.wrapping_add(addend),
+            _ if reloc::SYMBOLIC.contains(&ty) => symbol_addr(elf, base, is32, symtab, sym)?.wrapping_add(addend),
+            _ => return Err("unsupported relocation"),
+        };
+        unsafe { write_word(target, value, is32); }
+    }
+    Ok(())
+}
+
+/// The address of the symbol `index` in the symbol table given by its (address, entry size)
+fn symbol_addr(elf: &ElfFile<'_>, base: usize, is32: bool, (addr, entry_size): (usize, usize),
+               index: usize) -> Result<usize, &'static str> {
+    const SHN_UNDEF: u16 = 0;
+    const SHN_ABS: u16 = 0xfff1;
+    let entry_size = match entry_size {
+        0 if is32 => 16,
+        0 => 24,
+        _ => entry_size,
+    };
+    let sym = index.checked_mul(entry_size).and_then(|offset| offset.checked_add(addr))
+        .ok_or("symbol is out of the segments")?;
+    check_loaded(elf, sym, entry_size)?;
+    let sym = base.wrapping_add(sym);
+    let (value, shndx) = unsafe {
+        match is32 {
+            true => (read_word(sym + 4, true), ((sym + 14) as *const u16).read_unaligned()),
+            false => (read_word(sym + 8, false), ((sym + 6) as *const u16).read_unaligned()),
+        }
+    };
+    match shndx {
+        SHN_UNDEF => Err("undefined symbol without an interpreter"),
+        SHN_ABS => Ok(value),
+        _ => Ok(base.wrapping_add(value)),
+    }
+}
+
+/// Check that `[addr, addr + size)` of the ELF is in a loaded segment
+fn check_loaded(elf: &ElfFile<'_>, addr: usize, size: usize) -> Result<(), &'static str> {
+    let end = addr.checked_add(size).ok_or("relocation is out of the segments")?;
+    let loaded = elf.program_iter()
+        .filter(|ph| ph.get_type() == Ok(Type::Load))
+        .any(|ph| ph.virtual_addr() as usize <= addr && end <= (ph.virtual_addr() + ph.mem_size()) as usize);
+    match loaded {
+        true => Ok(()),
+        false => Err("relocation is out of the segments"),
+    }
+}
+
+unsafe fn read_word(addr: usize, is32: bool) -> usize {
+    match is32 {
+        true => (addr as *const u32).read_unaligned() as usize,
+        false => (addr as *const u64).read_unaligned() as usize,
+    }
+}
+
+unsafe fn write_word(addr: usize, value: usize, is32: bool) {
+    match is32 {
+        true => (addr as *mut u32).write_unaligned(value as u32),
+        false => (addr as *mut u64).write_unaligned(value as u64),
+    }
+}
@@ -1,4 +1,4 @@
-pub use self::context::Process;
+pub use self::context::{Process, FileDesc, CloneFlags};
 pub use rcore_process::*;
 use crate::consts::{MAX_CPU_NUM, MAX_PROCESS_NUM};
 use crate::arch::cpu;
@@ -24,14 +24,33 @@ pub fn init() {
     }
     use core::str::FromStr;
     let cores = usize::from_str(env!("SMP")).unwrap();
-    for i in 0..cores {
-        manager.add(Process::new_kernel(idle, i), 0);
+    // pid 0 is the idle thread of CPU 0, and pid 1 is the init process
+    manager.add(Process::new_kernel(idle, 0), 0).unwrap();
+    let init_pid = manager.add(Process::new_kernel(init, 0), 0).unwrap();
+    assert_eq!(init_pid, INIT_PID);
+    for i in 1..cores {
+        manager.add(Process::new_kernel(idle, i), 0).expect("failed to add idle thread");
     }
-    crate::shell::run_user_shell();
 
     info!("process init end");
 }
 
+/// The init process. It starts the shell, adopts the orphans and reaps them when they exit.
+extern fn init(_arg: usize) -> ! {
+    crate::shell::run_user_shell();
+    let pid = thread::current().id();
+    loop {
+        for child in processor().manager().get_children(pid) {
+            if let Some(Status::Exited(_)) = processor().manager().get_status(child) {
+                info!("init: reap {}", child);
+                processor().manager().remove(child);
+            }
+        }
+        processor().manager().wait_child(pid);
+        processor().yield_now();
+    }
+}
+
 static PROCESSORS: [Processor; MAX_CPU_NUM] = [Processor::new(), Processor::new(), Processor::new(), Processor::new(), Processor::new(), Processor::new(), Processor::new(), Processor::new()];
 
 /// Get current thread struct
@@ -45,6 +64,15 @@ pub fn process() -> &'static mut Process {
     process
 }
 
+/// The status reported by `wait` for a process exited with `code`
+pub fn exit_status(code: usize) -> usize {
+    (code & 0xff) << 8
+}
+
+/// The status reported by `wait` for a process terminated by signal `sig`
+pub fn signal_status(sig: usize) -> usize {
+    sig & 0x7f
+}
 
 // Implement dependencies for std::thread
 
@@ -1,17 +1,21 @@
 //! Kernel shell
 
 use alloc::string::String;
-use crate::fs::{ROOT_INODE, INodeExt};
+use core::iter;
+use crate::fs::ROOT_INODE;
 use crate::process::*;
 
+/// Start the shell as a child of the current thread
 pub fn run_user_shell() {
     if let Ok(inode) = ROOT_INODE.lookup("sh") {
         println!("Going to user mode shell.");
         println!("Use 'ls' to list available programs.");
-        let data = inode.read_as_vec().unwrap();
-        processor().manager().add(Process::new_user(data.as_slice(), "sh".split(' ')), 0);
+        let context = Process::new_user(&inode, "sh".split(' '), iter::empty()).expect("failed to load the shell");
+        processor().manager().add(context, thread::current().id())
+            .expect("failed to add the shell");
     } else {
-        processor().manager().add(Process::new_kernel(shell, 0), 0);
+        processor().manager().add(Process::new_kernel(shell, 0), thread::current().id())
+            .expect("failed to add the shell");
     }
 }
 
@@ -27,9 +31,17 @@ pub extern fn shell(_arg: usize) -> ! {
         }
         let name = cmd.split(' ').next().unwrap();
         if let Ok(file) = ROOT_INODE.lookup(name) {
-            let data = file.read_as_vec().unwrap();
-            let pid = processor().manager().add(Process::new_user(data.as_slice(), cmd.split(' ')), thread::current().id());
-            unsafe { thread::JoinHandle::<()>::_of(pid) }.join().unwrap();
+            let context = match Process::new_user(&file, cmd.split(' '), iter::empty()) {
+                Ok(context) => context,
+                Err(e) => {
+                    println!("Failed to load program: {}", e);
+                    continue;
+                }
+            };
+            match processor().manager().add(context, thread::current().id()) {
+                Some(pid) => unsafe { thread::JoinHandle::<()>::_of(pid) }.join().unwrap(),
+                None => println!("Process number exceeded"),
+            }
         } else {
             println!("Program not exist");
         }
@@ -0,0 +1,182 @@
+//! POSIX-style signals
+//!
+//! Pending signals are kept by the `ProcessManager`, and delivered when a thread returns to user mode.
+//! A handler runs on the user stack above a `SignalFrame` which saves the interrupted context.
+//! It returns to the `restorer` of its action, which calls `sigreturn` to restore the context.
+
+use core::mem::size_of;
+use bitflags::bitflags;
+use log::*;
+use crate::arch::interrupt::TrapFrame;
+use crate::process::*;
+use crate::thread;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGWINCH: usize = 28;
+
+/// Signals are numbered in `1..NSIG`. Bit `n` of a mask is signal `n`.
+pub const NSIG: usize = 32;
+
+/// Signals which can't be caught, blocked or ignored
+const UNBLOCKABLE: usize = 1 << SIGKILL | 1 << SIGSTOP;
+
+/// Handler for the default action
+pub const SIG_DFL: usize = 0;
+/// Handler to ignore the signal
+pub const SIG_IGN: usize = 1;
+
+bitflags! {
+    pub struct SigActionFlags: usize {
+        /// Don't block the signal in its handler
+        const NODEFER = 0x40000000;
+        /// Reset the action to default when the handler is called
+        const RESETHAND = 0x80000000;
+    }
+}
+
+/// The action to take when a signal is delivered
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN` or the address of `extern fn(sig: usize)`
+    pub handler: usize,
+    pub flags: usize,
+    /// Signals to block in the handler
+    pub mask: usize,
+    /// Where the handler returns to, which should call `sigreturn`
+    pub restorer: usize,
+}
+
+/// The context of a thread interrupted by a signal handler, saved on the user stack
+#[repr(C)]
+struct SignalFrame {
+    regs: [usize; 32],
+    pc: usize,
+    mask: usize,
+}
+
+/// Whether `sig` is ignored by the default action. Otherwise it terminates the process.
+/// Stopping a process is not supported, so the stop signals are ignored.
+fn ignored_by_default(sig: usize) -> bool {
+    match sig {
+        SIGCHLD | SIGCONT | SIGURG | SIGWINCH | SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => true,
+        _ => false,
+    }
+}
+
+/// Whether `sig` is a valid signal number
+pub fn is_valid(sig: usize) -> bool {
+    sig > 0 && sig < NSIG
+}
+
+/// Whether the action of `sig` can be changed
+pub fn is_catchable(sig: usize) -> bool {
+    is_valid(sig) && UNBLOCKABLE & (1 << sig) == 0
+}
+
+/// Change the signal mask of the current thread
+pub fn set_mask(mask: usize) {
+    process().sig_mask = mask & !UNBLOCKABLE;
+}
+
+/// Send `sig` caused by a fault to the current thread.
+/// It is unblocked and not ignored, or the thread would fault again forever.
+pub fn send_fault_signal(sig: usize) {
+    let proc = process();
+    proc.sig_mask &= !(1 << sig);
+    {
+        let mut actions = proc.sig_actions.lock();
+        if actions[sig].handler == SIG_IGN {
+            actions[sig] = SigAction::default();
+        }
+    }
+    processor().manager().send_signal(thread::current().id(), sig);
+}
+
+/// Deliver the pending signals of the current thread before it returns to user mode.
+/// Modify `tf` to run the first caught one, or terminate the process by the default action.
+pub fn handle_signals(tf: &mut TrapFrame) {
+    let pid = thread::current().id();
+    while let Some(sig) = processor().manager().take_signal(pid, process().sig_mask) {
+        let action = process().sig_actions.lock()[sig];
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL if ignored_by_default(sig) => {}
+            SIG_DFL => terminate(pid, sig),
+            _ => {
+                if push_signal_frame(tf, sig, &action).is_err() {
+                    warn!("failed to push signal frame of {} for pid {}", sig, pid);
+                    terminate(pid, SIGSEGV);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Set up `tf` to run the handler of `sig`, saving the current context on the user stack.
+fn push_signal_frame(tf: &mut TrapFrame, sig: usize, action: &SigAction) -> Result<(), ()> {
+    let proc = process();
+    let sp = tf.x[2].checked_sub(size_of::<SignalFrame>()).ok_or(())? & !0xf;
+    let frame = sp as *mut SignalFrame;
+    proc.memory_set.lock().check_write_ptr(frame).map_err(|_| ())?;
+    unsafe { frame.write(SignalFrame { regs: tf.x, pc: tf.sepc, mask: proc.sig_mask }); }
+    info!("signal: deliver {} to pid {}, handler: {:#x}", sig, thread::current().id(), action.handler);
+
+    let flags = SigActionFlags::from_bits_truncate(action.flags);
+    let mut mask = proc.sig_mask | action.mask;
+    if !flags.contains(SigActionFlags::NODEFER) {
+        mask |= 1 << sig;
+    }
+    set_mask(mask);
+    if flags.contains(SigActionFlags::RESETHAND) {
+        proc.sig_actions.lock()[sig] = SigAction::default();
+    }
+
+    tf.x[2] = sp;
+    tf.x[10] = sig; // a0
+    tf.x[1] = action.restorer; // ra
+    tf.sepc = action.handler;
+    Ok(())
+}
+
+/// Restore the context saved before the signal handler, which returned with the stack pointer in `tf`.
+pub fn sigreturn(tf: &mut TrapFrame) -> Result<(), ()> {
+    let frame = tf.x[2] as *const SignalFrame;
+    process().memory_set.lock().check_read_ptr(frame).map_err(|_| ())?;
+    let frame = unsafe { frame.read() };
+    // only the user registers are restored, never the privileged status
+    tf.x = frame.regs;
+    tf.sepc = frame.pc;
+    set_mask(frame.mask);
+    Ok(())
+}
+
+/// Terminate the thread group of process `pid` by signal `sig`
+fn terminate(pid: usize, sig: usize) -> ! {
+    info!("signal: pid {} terminated by {}", pid, sig);
+    processor().manager().exit_group(pid, signal_status(sig));
+    processor().yield_now();
+    unreachable!();
+}
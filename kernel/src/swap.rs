@@ -0,0 +1,261 @@
+//! Swap user pages out to a ramdisk when the frames are exhausted
+//!
+//! A user page is set swappable when a page fault brings it into memory.
+//! When the frame allocator runs out, `swap_out_any` writes a page chosen by the swap manager
+//! to the ramdisk and reuses its frame. The page is swapped in on its next page fault.
+//!
+//! The swap manager is selected by `swap` in the Makefile: fifo, enhanced_clock, aging or wsclock.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::ops::Bound::{Excluded, Included, Unbounded};
+use bit_allocator::{BitAlloc, BitAlloc256};
+use lazy_static::lazy_static;
+use log::*;
+use rcore_memory::{Frame, Page, PhysAddr, VirtAddr, PAGE_SIZE};
+use rcore_memory::paging::{Entry, InactivePageTable, PageTable};
+use rcore_memory::swap::{SwapExt, SwapManager, Swapper};
+use crate::consts::SWAP_SIZE;
+use crate::memory::{ActivePageTable, InactivePageTable0, MemorySet, active_table, alloc_frame, dealloc_frame, redirected_token};
+use crate::sync::SpinNoIrqLock;
+
+#[cfg(feature = "swap_enhanced_clock")]
+type InnerSwapManager = rcore_memory::swap::enhanced_clock::EnhancedClockSwapManager;
+#[cfg(feature = "swap_aging")]
+type InnerSwapManager = rcore_memory::swap::aging::AgingSwapManager;
+#[cfg(feature = "swap_wsclock")]
+type InnerSwapManager = rcore_memory::swap::wsclock::WSClockSwapManager;
+#[cfg(not(any(feature = "swap_enhanced_clock", feature = "swap_aging", feature = "swap_wsclock")))]
+type InnerSwapManager = rcore_memory::swap::fifo::FifoSwapManager;
+
+/// The ticks of the working set window of WSClock
+#[cfg(feature = "swap_wsclock")]
+const WSCLOCK_TAU: usize = 100;
+
+#[cfg(feature = "swap_wsclock")]
+fn new_inner_swap_manager() -> InnerSwapManager {
+    InnerSwapManager::new(WSCLOCK_TAU)
+}
+
+#[cfg(not(feature = "swap_wsclock"))]
+fn new_inner_swap_manager() -> InnerSwapManager {
+    InnerSwapManager::default()
+}
+
+const SWAP_SLOTS: usize = SWAP_SIZE / PAGE_SIZE;
+
+/// Swap space in the ramdisk, a page per slot
+struct RamSwapper {
+    /// The data of the ramdisk
+    disk: Vec<u8>,
+    /// Free slots
+    slots: BitAlloc256,
+    /// The token of the page table of the page in each used slot
+    owners: [usize; SWAP_SLOTS],
+}
+
+impl RamSwapper {
+    fn new() -> Self {
+        let mut slots = BitAlloc256::default();
+        slots.insert(0..SWAP_SLOTS);
+        RamSwapper { disk: vec![0; SWAP_SIZE], slots, owners: [0; SWAP_SLOTS] }
+    }
+
+    fn slot(&mut self, id: usize) -> Result<&mut [u8], ()> {
+        if id >= SWAP_SLOTS || self.slots.test(id) {
+            return Err(());
+        }
+        Ok(&mut self.disk[id * PAGE_SIZE..(id + 1) * PAGE_SIZE])
+    }
+
+    /// Free the slots of the pages of the page table `token`
+    fn discard(&mut self, token: usize) {
+        for id in 0..SWAP_SLOTS {
+            if !self.slots.test(id) && self.owners[id] == token {
+                self.slots.dealloc(id);
+            }
+        }
+    }
+}
+
+impl Swapper for RamSwapper {
+    fn swap_out(&mut self, data: &[u8]) -> Result<usize, ()> {
+        let id = self.slots.alloc().ok_or(())?;
+        // called with the page table of the page activated
+        self.owners[id] = InactivePageTable0::active_token();
+        self.slot(id)?.copy_from_slice(data);
+        Ok(id)
+    }
+
+    fn swap_update(&mut self, token: usize, data: &[u8]) -> Result<(), ()> {
+        self.slot(token)?.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn swap_in(&mut self, token: usize, data: &mut [u8]) -> Result<(), ()> {
+        data.copy_from_slice(self.slot(token)?);
+        self.slots.dealloc(token);
+        Ok(())
+    }
+}
+
+/// Swap manager keeping the pages of each page table in an inner swap manager of its own,
+/// since an inner one checks the entries of its pages through the current page table.
+/// The victims are chosen from the page tables in turn.
+struct KernelSwapManager {
+    /// The raw pointers of the live page tables with swappable pages and their swap managers, by their tokens
+    tables: BTreeMap<usize, (usize, InnerSwapManager)>,
+    /// The token of the page table of the last victim
+    last: usize,
+}
+
+impl KernelSwapManager {
+    fn new() -> Self {
+        KernelSwapManager { tables: BTreeMap::new(), last: 0 }
+    }
+}
+
+impl SwapManager for KernelSwapManager {
+    fn tick<T: PageTable>(&mut self, page_table: &mut T) {
+        for (&token, (pt, manager)) in self.tables.iter_mut() {
+            with_table(token, *pt, || manager.tick(page_table));
+        }
+    }
+
+    fn push(&mut self, frame: Frame) {
+        let pt = frame.get_page_table();
+        self.tables.entry(frame.get_token())
+            .or_insert_with(|| (pt, new_inner_swap_manager()))
+            .1.push(frame);
+    }
+
+    fn remove(&mut self, token: usize, addr: VirtAddr) {
+        if let Some((_, manager)) = self.tables.get_mut(&token) {
+            manager.remove(token, addr);
+        }
+    }
+
+    fn pop<T, S>(&mut self, page_table: &mut T, swapper: &mut S) -> Option<Frame>
+        where T: PageTable, S: Swapper
+    {
+        // the page tables after the one of the last victim first
+        let tokens: Vec<usize> = self.tables.range((Excluded(self.last), Unbounded))
+            .chain(self.tables.range((Unbounded, Included(self.last))))
+            .map(|(&token, _)| token)
+            .collect();
+        for token in tokens {
+            let (pt, manager) = self.tables.get_mut(&token).unwrap();
+            if let Some(Some(frame)) = with_table(token, *pt, || manager.pop(page_table, swapper)) {
+                self.last = token;
+                return Some(frame);
+            }
+        }
+        None
+    }
+}
+
+/// Run `f` in the page table `pt` of `token` with its memory set locked.
+/// Return `None` if the memory set is busy now, or the page table is being edited.
+/// Called with `SWAP` locked, so the memory set can't be freed meanwhile.
+fn with_table<T>(token: usize, pt: usize, f: impl FnOnce() -> T) -> Option<T> {
+    // the recursive mapping of the page table is redirected by `edit` now
+    if token == redirected_token() {
+        return None;
+    }
+    let _guard = memory_set_of(token)?.try_lock()?;
+    Some(unsafe { (*(pt as *const InactivePageTable0)).with(f) })
+}
+
+/// The lock of the memory set owning the page table `token`.
+/// It can't be freed while `SWAP` is locked, since `forget_table` waits for `SWAP`.
+fn memory_set_of(token: usize) -> Option<&'static SpinNoIrqLock<MemorySet>> {
+    MEMORY_SETS.lock().get(&token)
+        .map(|&memory_set| unsafe { &*(memory_set as *const SpinNoIrqLock<MemorySet>) })
+}
+
+lazy_static! {
+    /// The raw pointers of the locks of the memory sets with swappable pages, by the tokens of their page tables.
+    /// A memory set is swapped out with its lock held, like the other changes of it.
+    static ref MEMORY_SETS: SpinNoIrqLock<BTreeMap<usize, usize>> = SpinNoIrqLock::new(BTreeMap::new());
+}
+
+lazy_static! {
+    static ref SWAP: SpinNoIrqLock<SwapExt<ActivePageTable, KernelSwapManager, RamSwapper>> =
+        SpinNoIrqLock::new(SwapExt::new(unsafe { ActivePageTable::new() }, KernelSwapManager::new(), RamSwapper::new()));
+}
+
+/// Swap out a user page and return its frame. Called when the frames are exhausted.
+/// A page whose memory set is locked now is skipped, e.g. the current one being changed.
+pub fn swap_out_any() -> Option<PhysAddr> {
+    let ret = SWAP.lock().swap_out_any_with::<InactivePageTable0>(|frame, swap_out| {
+        match memory_set_of(frame.get_token()).and_then(|memory_set| memory_set.try_lock()) {
+            Some(_guard) => {
+                swap_out();
+                true
+            }
+            None => false,
+        }
+    });
+    match ret {
+        Ok(frame) => Some(frame),
+        Err(e) => {
+            warn!("failed to swap out a page: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Update the state of the swap manager. Called by the timer interrupt.
+pub fn tick() {
+    SWAP.lock().tick();
+}
+
+/// Set the page at `addr` of the current page table `pt` swappable,
+/// if it is a user page in memory and not shared.
+/// `memory_set` is the lock of the memory set owning `pt`.
+pub fn set_swappable(memory_set: &SpinNoIrqLock<MemorySet>, pt: *mut InactivePageTable0, addr: VirtAddr) {
+    let swappable = active_table().get_entry(addr).map_or(false, |entry| {
+        entry.present() && entry.user() && !entry.readonly_shared() && !entry.writable_shared()
+    });
+    if swappable {
+        let token = unsafe { (*pt).token() };
+        MEMORY_SETS.lock().insert(token, memory_set as *const _ as usize);
+        unsafe { SWAP.lock().set_swappable(pt, addr & !(PAGE_SIZE - 1)); }
+    }
+}
+
+/// Swap in the page at `addr` of the current page table `pt` if it is swapped out.
+/// Return whether it is swapped in.
+pub fn swap_in(pt: *mut InactivePageTable0, addr: VirtAddr) -> bool {
+    let addr = addr & !(PAGE_SIZE - 1);
+    if !active_table().get_entry(addr).map_or(false, |entry| entry.swapped()) {
+        return false;
+    }
+    // allocating may swap out another page, so do it before locking
+    let frame = alloc_frame().expect("failed to allocate frame");
+    if SWAP.lock().page_fault_handler(pt, addr, true, || frame) {
+        return true;
+    }
+    dealloc_frame(frame);
+    false
+}
+
+/// Swap in all the pages of the current memory set.
+/// Fork only shares the pages in memory with the new memory set, so it is called before.
+pub fn swap_in_all(memory_set: &mut MemorySet) {
+    let pages: Vec<VirtAddr> = memory_set.iter()
+        .flat_map(|area| Page::range_of(area.get_start_addr(), area.get_end_addr()))
+        .map(|page| page.start_address())
+        .collect();
+    let pt = memory_set.get_page_table_mut() as *mut InactivePageTable0;
+    for addr in pages {
+        swap_in(pt, addr);
+    }
+}
+
+/// Forget the pages of the page table `token` which is being dropped
+pub fn forget_table(token: usize) {
+    let mut swap = SWAP.lock();
+    MEMORY_SETS.lock().remove(&token);
+    swap.swap_manager_mut().tables.remove(&token);
+    swap.swapper_mut().discard(token);
+}
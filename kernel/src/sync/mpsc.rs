@@ -121,7 +121,7 @@ pub mod test {
         let (tx, rx) = channel::<i32>();
         let _t = thread::spawn(move || {
             tx.send(1).unwrap();
-        });
+        }).unwrap();
         assert_eq!(rx.recv().unwrap(), 1);
     }
 
@@ -102,7 +102,7 @@ fn philosopher(table: Arc<Table>) {
                 p.eat(&table);
                 println!("{} iter {} end.", p.name, i);
             }
-        })
+        }).expect("failed to spawn philosopher")
     }).collect();
     trace!("philosopher starting finish");
 
@@ -1,15 +1,19 @@
 //! System call
 
-use simple_filesystem::{INode, file::File, FileInfo, FileType, FsError};
+use simple_filesystem::{INode, FileInfo, FileType, FsError};
 use core::{slice, str};
 use alloc::{sync::Arc, vec::Vec, string::String};
 use spin::Mutex;
 use log::*;
 use bitflags::bitflags;
+use rcore_memory::{PAGE_SIZE, memory_set::VMError};
 use crate::arch::interrupt::TrapFrame;
+use crate::consts::{USER_MMAP_OFFSET, USER_STACK_OFFSET};
+use crate::memory::{Delay, GlobalFrameAlloc, KernelStackError, MemoryAttr};
 use crate::process::*;
+use crate::fs::{FileHandle, OpenOptions, SeekFrom, Pipe};
+use crate::signal::{self, SigAction, SIGKILL, SIGPIPE, SIGSEGV, SIG_IGN};
 use crate::thread;
-use crate::util;
 
 /// System call dispatcher
 pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
@@ -20,29 +24,41 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         102 => sys_read(args[0], args[1] as *mut u8, args[2]),
         103 => sys_write(args[0], args[1] as *const u8, args[2]),
         030 => sys_putc(args[0] as u8 as char),
-//        104 => sys_seek(),
+        104 => sys_seek(args[0], args[1] as isize, args[2] as u8),
         110 => sys_fstat(args[0], args[1] as *mut Stat),
-//        111 => sys_fsync(),
-//        121 => sys_getcwd(),
+        111 => sys_fsync(args[0]),
+        120 => sys_chdir(args[0] as *const u8),
+        121 => sys_getcwd(args[0] as *mut u8, args[1]),
+        122 => sys_mkdir(args[0] as *const u8),
+        123 => sys_rmdir(args[0] as *const u8),
+        124 => sys_link(args[0] as *const u8, args[1] as *const u8),
+        125 => sys_unlink(args[0] as *const u8),
+        126 => sys_rename(args[0] as *const u8, args[1] as *const u8),
         128 => sys_getdirentry(args[0], args[1] as *mut DirEntry),
         130 => sys_dup(args[0], args[1]),
+        140 => sys_pipe(args[0] as *mut u32),
 
         // process
         001 => sys_exit(args[0] as isize),
         002 => sys_fork(tf),
-        003 => sys_wait(args[0], args[1] as *mut i32),
-        004 => sys_exec(args[0] as *const u8, args[1] as usize, args[2] as *const *const u8, tf),
-//        005 => sys_clone(),
+        003 => sys_wait(args[0], args[1] as *mut i32, args[2]),
+        004 => sys_exec(args[0] as *const u8, args[1] as usize, args[2] as *const *const u8, args[3] as *const *const u8, tf),
+        005 => sys_clone(args[0], args[1], tf),
         010 => sys_yield(),
         011 => sys_sleep(args[0]),
-        012 => sys_kill(args[0]),
+        012 => sys_kill(args[0], args[1]),
+        013 => sys_sigaction(args[0], args[1] as *const SigAction, args[2] as *mut SigAction),
+        014 => sys_sigreturn(tf),
+        015 => sys_sigprocmask(args[0], args[1] as *const usize, args[2] as *mut usize),
         017 => sys_get_time(),
         018 => sys_getpid(),
         255 => sys_lab6_set_priority(args[0]),
 
         // memory
-//        020 => sys_mmap(),
-//        021 => sys_munmap(),
+        020 => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
+        021 => sys_munmap(args[0], args[1]),
+        023 => sys_mprotect(args[0], args[1], args[2]),
+        024 => sys_brk(args[0]),
 //        022 => sys_shmem(),
 //        031 => sys_pgdir(),
 
@@ -58,51 +74,181 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
 }
 
 fn sys_read(fd: usize, base: *mut u8, len: usize) -> SysResult {
-    // TODO: check ptr
     info!("read: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
+    process().memory_set.lock().check_write_array(base, len)?;
     let slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let len = get_file(fd)?.lock().read(slice)?;
-    Ok(len as isize)
+    let file = get_file(fd)?;
+    let is_pipe = file.lock().is_pipe();
+    let mut buf = vec![0u8; len.min(IO_BUF_SIZE)];
+    let mut read = 0;
+    while read < len {
+        let end = len.min(read + IO_BUF_SIZE);
+        let chunk = &mut buf[..end - read];
+        let chunk_len = match read_file(&file, chunk) {
+            Ok(chunk_len) => chunk_len,
+            Err(e) if read == 0 => return Err(e),
+            Err(_) => break,
+        };
+        slice[read..read + chunk_len].copy_from_slice(&chunk[..chunk_len]);
+        read += chunk_len;
+        // a pipe returns once some data is read
+        if read < end || is_pipe {
+            break;
+        }
+    }
+    Ok(read as isize)
 }
 
 fn sys_write(fd: usize, base: *const u8, len: usize) -> SysResult {
-    // TODO: check ptr
     info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
+    process().memory_set.lock().check_read_array(base, len)?;
     let slice = unsafe { slice::from_raw_parts(base, len) };
-    let len = get_file(fd)?.lock().write(slice)?;
-    Ok(len as isize)
+    let file = get_file(fd)?;
+    let mut buf = vec![0u8; len.min(IO_BUF_SIZE)];
+    let mut written = 0;
+    while written < len {
+        let end = len.min(written + IO_BUF_SIZE);
+        let chunk = &mut buf[..end - written];
+        chunk.copy_from_slice(&slice[written..end]);
+        let chunk_len = match write_file(&file, chunk) {
+            Ok(chunk_len) => chunk_len,
+            Err(_) if written != 0 => break,
+            Err(SysError::Pipe) => {
+                processor().manager().send_signal(thread::current().id(), SIGPIPE);
+                return Err(SysError::Pipe);
+            }
+            Err(e) => return Err(e),
+        };
+        written += chunk_len;
+        if written < end {
+            break;
+        }
+    }
+    Ok(written as isize)
+}
+
+/// The size of the kernel buffer of read and write.
+/// A user buffer is never passed to a file, since a page fault on it may read a file mapping,
+/// which reenters the file system.
+const IO_BUF_SIZE: usize = PAGE_SIZE * 4;
+
+/// Read the file into the kernel buffer `buf`
+fn read_file(file: &Mutex<FileHandle>, buf: &mut [u8]) -> Result<usize, SysError> {
+    let mut file = file.lock();
+    if !file.is_pipe() {
+        return Ok(file.read(buf)?);
+    }
+    // a pipe has no offset, so read it through a copy of the handle without holding the lock
+    // while blocking, since the writer may share the handle after fork or dup
+    let mut pipe = file.clone();
+    drop(file);
+    Ok(pipe.read(buf)?)
+}
+
+/// Write the kernel buffer `buf` to the file.
+/// Return `SysError::Pipe` if it's a broken pipe.
+fn write_file(file: &Mutex<FileHandle>, buf: &[u8]) -> Result<usize, SysError> {
+    let mut file = file.lock();
+    if !file.is_pipe() {
+        return Ok(file.write(buf)?);
+    }
+    // the same as reading, the reader may share the handle
+    let mut pipe = file.clone();
+    drop(file);
+    match pipe.write(buf) {
+        Ok(len) => Ok(len),
+        Err(_) if pipe.is_broken_pipe() => Err(SysError::Pipe),
+        Err(e) => Err(e.into()),
+    }
 }
 
 fn sys_open(path: *const u8, flags: usize) -> SysResult {
-    // TODO: check ptr
-    let path = unsafe { util::from_cstr(path) };
+    let path = check_and_clone_cstr(path)?;
     let flags = VfsFlags::from_ucore_flags(flags);
     info!("open: path: {:?}, flags: {:?}", path, flags);
-    let (fd, inode) = match path {
-        "stdin:" => (0, crate::fs::STDIN.clone() as Arc<INode>),
-        "stdout:" => (1, crate::fs::STDOUT.clone() as Arc<INode>),
+    let (fd, inode) = match path.as_str() {
+        "stdin:" => (Some(0), crate::fs::STDIN.clone() as Arc<INode>),
+        "stdout:" => (Some(1), crate::fs::STDOUT.clone() as Arc<INode>),
         _ => {
-            let fd = (3..).find(|i| !process().files.contains_key(i)).unwrap();
-            let inode = crate::fs::ROOT_INODE.lookup(path)?;
-            (fd, inode)
+            let inode = match flags.contains(VfsFlags::CREATE) {
+                true => {
+                    let (dir_inode, file_name) = lookup_parent(path.as_str())?;
+                    match dir_inode.find(file_name.as_str()) {
+                        Ok(_) if flags.contains(VfsFlags::EXCLUSIVE) => return Err(SysError::Exists),
+                        Ok(file_inode) => file_inode,
+                        Err(FsError::EntryNotFound) => dir_inode.create(file_name.as_str(), FileType::File)?,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                false => lookup_inode(path.as_str())?,
+            };
+            // only a file opened for writing is truncated
+            if flags.contains(VfsFlags::TRUNCATE) && flags.contains(VfsFlags::WRITABLE) {
+                if inode.info()?.type_ != FileType::File {
+                    return Err(SysError::Isdir);
+                }
+                inode.resize(0)?;
+            }
+            (None, inode)
+        }
+    };
+    let file = FileHandle::new(inode, OpenOptions {
+        read: flags.contains(VfsFlags::READABLE),
+        write: flags.contains(VfsFlags::WRITABLE),
+        append: flags.contains(VfsFlags::APPEND),
+    });
+    let desc = FileDesc { file: Arc::new(Mutex::new(file)), cloexec: flags.contains(VfsFlags::CLOEXEC) };
+    let fd = match fd {
+        Some(fd) => {
+            process().files.lock().insert(fd, desc);
+            fd
         }
+        None => add_file(desc),
     };
-    let file = File::new(inode, flags.contains(VfsFlags::READABLE), flags.contains(VfsFlags::WRITABLE));
-    process().files.insert(fd, Arc::new(Mutex::new(file)));
     Ok(fd as isize)
 }
 
+/// Move the offset of the file. Return the new offset.
+fn sys_seek(fd: usize, offset: isize, whence: u8) -> SysResult {
+    let pos = match whence {
+        SEEK_SET if offset < 0 => return Err(SysError::Inval),
+        SEEK_SET => SeekFrom::Start(offset as usize),
+        SEEK_CUR => SeekFrom::Current(offset),
+        SEEK_END => SeekFrom::End(offset),
+        _ => return Err(SysError::Inval),
+    };
+    info!("seek: fd: {}, pos: {:?}", fd, pos);
+    let file = get_file(fd)?;
+    let mut file = file.lock();
+    if file.is_pipe() {
+        return Err(SysError::Seek);
+    }
+    let offset = file.seek(pos)?;
+    Ok(offset as isize)
+}
+
+const SEEK_SET: u8 = 0;
+const SEEK_CUR: u8 = 1;
+const SEEK_END: u8 = 2;
+
+/// Write the data of the file to the device.
+fn sys_fsync(fd: usize) -> SysResult {
+    info!("fsync: fd: {}", fd);
+    get_file(fd)?.lock().sync()?;
+    Ok(0)
+}
+
 fn sys_close(fd: usize) -> SysResult {
     info!("close: fd: {:?}", fd);
-    match process().files.remove(&fd) {
+    match process().files.lock().remove(&fd) {
         Some(_) => Ok(0),
         None => Err(SysError::Inval),
     }
 }
 
 fn sys_fstat(fd: usize, stat_ptr: *mut Stat) -> SysResult {
-    // TODO: check ptr
     info!("fstat: {}", fd);
+    process().memory_set.lock().check_write_ptr(stat_ptr)?;
     let file = get_file(fd)?;
     let stat = Stat::from(file.lock().info()?);
     unsafe { stat_ptr.write(stat); }
@@ -113,8 +259,8 @@ fn sys_fstat(fd: usize, stat_ptr: *mut Stat) -> SysResult {
 /// dentry.name = entry_name
 /// dentry.offset += 256
 fn sys_getdirentry(fd: usize, dentry_ptr: *mut DirEntry) -> SysResult {
-    // TODO: check ptr
     info!("getdirentry: {}", fd);
+    process().memory_set.lock().check_write_ptr(dentry_ptr)?;
     let file = get_file(fd)?;
     let dentry = unsafe { &mut *dentry_ptr };
     if !dentry.check() {
@@ -129,90 +275,247 @@ fn sys_getdirentry(fd: usize, dentry_ptr: *mut DirEntry) -> SysResult {
     Ok(0)
 }
 
+/// Copy the working directory to `buf` as a C string.
+fn sys_getcwd(buf: *mut u8, len: usize) -> SysResult {
+    info!("getcwd: buf: {:?}, len: {:#x}", buf, len);
+    process().memory_set.lock().check_write_array(buf, len)?;
+    let cwd = process().cwd.lock().path.clone();
+    let cwd = cwd.as_bytes();
+    if cwd.len() + 1 > len {
+        return Err(SysError::Inval);
+    }
+    let buf = unsafe { slice::from_raw_parts_mut(buf, cwd.len() + 1) };
+    buf[..cwd.len()].copy_from_slice(cwd);
+    buf[cwd.len()] = 0;
+    Ok(0)
+}
+
+/// Change the working directory to `path`.
+fn sys_chdir(path: *const u8) -> SysResult {
+    let path = check_and_clone_cstr(path)?;
+    info!("chdir: path: {:?}", path);
+    let inode = lookup_inode(path.as_str())?;
+    if inode.info()?.type_ != FileType::Dir {
+        return Err(SysError::Notdir);
+    }
+    let mut cwd = process().cwd.lock();
+    cwd.path = crate::fs::resolve_path(&cwd.path, path.as_str());
+    cwd.inode = inode;
+    Ok(0)
+}
+
+/// Create a pipe. Store the read fd and the write fd to `fds`.
+fn sys_pipe(fds: *mut u32) -> SysResult {
+    info!("pipe: fds: {:?}", fds);
+    process().memory_set.lock().check_write_array(fds, 2)?;
+    let (read, write) = Pipe::new_pair();
+    let read_fd = add_inode(Arc::new(read), OpenOptions { read: true, write: false, append: false });
+    let write_fd = add_inode(Arc::new(write), OpenOptions { read: false, write: true, append: false });
+    unsafe {
+        fds.write(read_fd as u32);
+        fds.add(1).write(write_fd as u32);
+    }
+    Ok(0)
+}
+
+/// Create a directory at `path`.
+fn sys_mkdir(path: *const u8) -> SysResult {
+    let path = check_and_clone_cstr(path)?;
+    info!("mkdir: path: {:?}", path);
+    let (dir_inode, name) = lookup_parent(path.as_str())?;
+    if dir_inode.find(name.as_str()).is_ok() {
+        return Err(SysError::Exists);
+    }
+    dir_inode.create(name.as_str(), FileType::Dir)?;
+    Ok(0)
+}
+
+/// Remove the empty directory at `path`.
+fn sys_rmdir(path: *const u8) -> SysResult {
+    let path = check_and_clone_cstr(path)?;
+    info!("rmdir: path: {:?}", path);
+    let (dir_inode, name) = lookup_parent(path.as_str())?;
+    let info = dir_inode.find(name.as_str())?.info()?;
+    if info.type_ != FileType::Dir {
+        return Err(SysError::Notdir);
+    }
+    // the file system refuses to unlink a directory that isn't empty
+    dir_inode.unlink(name.as_str())?;
+    Ok(0)
+}
+
+/// Make a new name `new_path` for the file at `old_path`.
+fn sys_link(old_path: *const u8, new_path: *const u8) -> SysResult {
+    let old_path = check_and_clone_cstr(old_path)?;
+    let new_path = check_and_clone_cstr(new_path)?;
+    info!("link: old_path: {:?}, new_path: {:?}", old_path, new_path);
+    let inode = lookup_inode(old_path.as_str())?;
+    let (dir_inode, name) = lookup_parent(new_path.as_str())?;
+    dir_inode.link(name.as_str(), &inode)?;
+    Ok(0)
+}
+
+/// Remove the name `path` of a file.
+fn sys_unlink(path: *const u8) -> SysResult {
+    let path = check_and_clone_cstr(path)?;
+    info!("unlink: path: {:?}", path);
+    let (dir_inode, name) = lookup_parent(path.as_str())?;
+    if dir_inode.find(name.as_str())?.info()?.type_ == FileType::Dir {
+        return Err(SysError::Isdir);
+    }
+    dir_inode.unlink(name.as_str())?;
+    Ok(0)
+}
+
+/// Move the file at `old_path` to `new_path`.
+fn sys_rename(old_path: *const u8, new_path: *const u8) -> SysResult {
+    let old_path = check_and_clone_cstr(old_path)?;
+    let new_path = check_and_clone_cstr(new_path)?;
+    info!("rename: old_path: {:?}, new_path: {:?}", old_path, new_path);
+    let (old_dir_inode, old_name) = lookup_parent(old_path.as_str())?;
+    let (new_dir_inode, new_name) = lookup_parent(new_path.as_str())?;
+    old_dir_inode.move_(old_name.as_str(), &new_dir_inode, new_name.as_str())?;
+    Ok(0)
+}
+
 fn sys_dup(fd1: usize, fd2: usize) -> SysResult {
     info!("dup: {} {}", fd1, fd2);
     let file = get_file(fd1)?;
-    if process().files.contains_key(&fd2) {
+    let mut files = process().files.lock();
+    if files.contains_key(&fd2) {
         return Err(SysError::Inval);
     }
-    process().files.insert(fd2, file.clone());
+    // the close-on-exec flag is not duplicated
+    files.insert(fd2, FileDesc { file, cloexec: false });
     Ok(0)
 }
 
 /// Fork the current process. Return the child's PID.
 fn sys_fork(tf: &TrapFrame) -> SysResult {
-    let context = process().fork(tf);
-    let pid = processor().manager().add(context, thread::current().id());
+    let context = process().fork(tf)?;
+    let pid = processor().manager().add(context, thread::current().id())
+        .ok_or(SysError::NoFreeProc)?;
     info!("fork: {} -> {}", thread::current().id(), pid);
     Ok(pid as isize)
 }
 
-/// Wait the process exit.
-/// Return the PID. Store exit code to `code` if it's not null.
-fn sys_wait(pid: usize, code: *mut i32) -> SysResult {
-    // TODO: check ptr
+/// Create a new thread sharing the resources selected by `flags`.
+/// The new thread runs on the user stack `newsp`. It can be 0 only without CLONE_VM,
+/// then the new thread runs on the caller's stack in its own copy of the memory.
+/// Return the new thread's PID.
+fn sys_clone(flags: usize, newsp: usize, tf: &TrapFrame) -> SysResult {
+    let flags = CloneFlags::from_bits_truncate(flags);
+    info!("clone: flags: {:?}, newsp: {:#x}", flags, newsp);
+    // two threads on the same stack of a shared memory would corrupt each other
+    if flags.contains(CloneFlags::VM) && newsp == 0 {
+        return Err(SysError::Inval);
+    }
+    // threads in a group must share the signal actions, which must be in a shared address space
+    if (flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::SIGHAND))
+        || (flags.contains(CloneFlags::SIGHAND) && !flags.contains(CloneFlags::VM)) {
+        return Err(SysError::Inval);
+    }
+    let context = process().clone(flags, newsp, tf)?;
+    let parent = thread::current().id();
+    let pid = match flags.contains(CloneFlags::THREAD) {
+        true => {
+            let tgid = processor().manager().get_tgid(parent).unwrap();
+            processor().manager().add_thread(context, parent, tgid)
+        }
+        false => processor().manager().add(context, parent),
+    }.ok_or(SysError::NoFreeProc)?;
+    info!("clone: {} -> {}", parent, pid);
+    Ok(pid as isize)
+}
+
+/// Wait for the child process `pid` to exit, or any child if `pid` is 0.
+/// Return its PID, or 0 if no child has exited with `WNOHANG`.
+/// Store its exit status to `status` if it's not null.
+fn sys_wait(pid: usize, status: *mut i32, options: usize) -> SysResult {
+    let options = WaitOptions::from_bits_truncate(options);
+    if !status.is_null() {
+        process().memory_set.lock().check_write_ptr(status)?;
+    }
+    let current = thread::current().id();
     loop {
-        use alloc::vec;
+        let children = processor().manager().get_children(current);
         let wait_procs = match pid {
-            0 => processor().manager().get_children(thread::current().id()),
-            _ => vec![pid],
+            0 => children,
+            _ if children.contains(&pid) => vec![pid],
+            _ => Vec::new(),
         };
         if wait_procs.is_empty() {
-            return Ok(-1);
+            return Err(SysError::BadProc);
         }
         for pid in wait_procs {
-            match processor().manager().get_status(pid) {
-                Some(Status::Exited(exit_code)) => {
-                    if !code.is_null() {
-                        unsafe { code.write(exit_code as i32); }
-                    }
-                    processor().manager().remove(pid);
-                    info!("wait: {} -> {}", thread::current().id(), pid);
-                    return Ok(0);
+            if let Some(Status::Exited(exit_status)) = processor().manager().get_status(pid) {
+                if !status.is_null() {
+                    unsafe { status.write(exit_status as i32); }
                 }
-                None => return Ok(-1),
-                _ => {}
+                processor().manager().remove(pid);
+                info!("wait: {} -> {}", current, pid);
+                return Ok(pid as isize);
             }
         }
-        info!("wait: {} -> {}, sleep", thread::current().id(), pid);
+        if options.contains(WaitOptions::NOHANG) {
+            return Ok(0);
+        }
+        info!("wait: {} -> {}, sleep", current, pid);
         if pid == 0 {
-            processor().manager().wait_child(thread::current().id());
-            processor().yield_now();
+            processor().manager().wait_child(current);
         } else {
-            processor().manager().wait(thread::current().id(), pid);
-            processor().yield_now();
+            processor().manager().wait(current, pid);
         }
+        processor().yield_now();
     }
 }
 
-fn sys_exec(name: *const u8, argc: usize, argv: *const *const u8, tf: &mut TrapFrame) -> SysResult {
-    // TODO: check ptr
-    let name = if name.is_null() { "" } else { unsafe { util::from_cstr(name) } };
-    info!("exec: {:?}, argc: {}, argv: {:?}", name, argc, argv);
-    // Copy args to kernel
-    let args: Vec<String> = unsafe {
-        slice::from_raw_parts(argv, argc).iter()
-            .map(|&arg| String::from(util::from_cstr(arg)))
-            .collect()
-    };
+/// Replace the current process with the program at `name`, or at the first arg if `name` is null.
+/// `envp` is a NULL-terminated array of environment variables, which may be null for an empty one.
+fn sys_exec(name: *const u8, argc: usize, argv: *const *const u8, envp: *const *const u8, tf: &mut TrapFrame) -> SysResult {
+    let name = if name.is_null() { String::new() } else { check_and_clone_cstr(name)? };
+    info!("exec: {:?}, argc: {}, argv: {:?}, envp: {:?}", name, argc, argv, envp);
+    // Copy args and envs to kernel
+    process().memory_set.lock().check_read_array(argv, argc)?;
+    let args: Vec<String> = unsafe { slice::from_raw_parts(argv, argc) }.iter()
+        .map(|&arg| check_and_clone_cstr(arg))
+        .collect::<Result<_, _>>()?;
+    let envs = check_and_clone_cstr_array(envp)?;
 
     if args.len() <= 0 {
         return Err(SysError::Inval);
     }
     // Read program file
-    let path = args[0].as_str();
-    let inode = crate::fs::ROOT_INODE.lookup(path)?;
-    let size = inode.info()?.size;
-    let mut buf = Vec::with_capacity(size);
-    unsafe { buf.set_len(size); }
-    inode.read_at(0, buf.as_mut_slice())?;
+    let path = if name.is_empty() { args[0].as_str() } else { name.as_str() };
+    let inode = lookup_inode(path)?;
+
+    // Make new Context. The current process is untouched if it fails.
+    let args = args.iter().map(|s| s.as_str());
+    let envs = envs.iter().map(|s| s.as_str());
+    let mut context = Process::new_user(&inode, args, envs).map_err(|e| {
+        warn!("exec: failed to load {:?}: {}", path, e);
+        SysError::InvalElf
+    })?;
 
-    // Make new Context
-    let iter = args.iter().map(|s| s.as_str());
-    let mut context = Process::new_user(buf.as_slice(), iter);
+    // Keep the opened files except the close-on-exec ones, and the working directory
+    let files = process().files.lock().iter()
+        .filter(|(_, desc)| !desc.cloexec)
+        .map(|(&fd, desc)| (fd, desc.clone()))
+        .collect();
+    *context.files.lock() = files;
+    let cwd = process().cwd.lock().clone();
+    *context.cwd.lock() = cwd;
+    // Keep the ignored signals and the signal mask, other actions are reset to default
+    let sig_actions = *process().sig_actions.lock();
+    for (new, old) in context.sig_actions.lock().iter_mut().zip(sig_actions.iter()) {
+        if old.handler == SIG_IGN {
+            *new = *old;
+        }
+    }
+    context.sig_mask = process().sig_mask;
 
     // Activate new page table
-    unsafe { context.memory_set.activate(); }
+    unsafe { context.memory_set.lock().activate(); }
 
     // Modify the TrapFrame
     *tf = unsafe { context.arch.get_init_tf() };
@@ -229,26 +532,110 @@ fn sys_yield() -> SysResult {
     Ok(0)
 }
 
-/// Kill the process
-fn sys_kill(pid: usize) -> SysResult {
-    info!("{} killed: {}", thread::current().id(), pid);
-    processor().manager().exit(pid, 0x100);
-    if pid == thread::current().id() {
-        processor().yield_now();
+/// Send signal `sig` to the process `pid`.
+/// Signal 0 only checks that the process exists.
+fn sys_kill(pid: usize, sig: usize) -> SysResult {
+    info!("kill: {} -> {}, signal: {}", thread::current().id(), pid, sig);
+    if sig != 0 && !signal::is_valid(sig) {
+        return Err(SysError::Inval);
+    }
+    match processor().manager().get_status(pid) {
+        None | Some(Status::Exited(_)) => return Err(SysError::Inval),
+        _ => {}
+    }
+    match sig {
+        0 => {}
+        // kill the thread group at once, even if it is blocked in the kernel
+        SIGKILL => {
+            let current = thread::current().id();
+            let in_group = processor().manager().get_tgid(pid) == processor().manager().get_tgid(current);
+            processor().manager().exit_group(pid, signal_status(SIGKILL));
+            if in_group {
+                processor().yield_now();
+            }
+        }
+        _ => processor().manager().send_signal(pid, sig),
     }
     Ok(0)
 }
 
-/// Get the current process id
+/// Set the action of signal `sig` to `act` if it's not null.
+/// Store the old action to `oldact` if it's not null.
+fn sys_sigaction(sig: usize, act: *const SigAction, oldact: *mut SigAction) -> SysResult {
+    info!("sigaction: sig: {}, act: {:?}, oldact: {:?}", sig, act, oldact);
+    if !signal::is_valid(sig) || (!act.is_null() && !signal::is_catchable(sig)) {
+        return Err(SysError::Inval);
+    }
+    if !act.is_null() {
+        process().memory_set.lock().check_read_ptr(act)?;
+    }
+    if !oldact.is_null() {
+        process().memory_set.lock().check_write_ptr(oldact)?;
+    }
+    let act = if act.is_null() { None } else { Some(unsafe { act.read() }) };
+    let old = process().sig_actions.lock()[sig];
+    if let Some(act) = act {
+        process().sig_actions.lock()[sig] = act;
+    }
+    if !oldact.is_null() {
+        unsafe { oldact.write(old); }
+    }
+    Ok(0)
+}
+
+/// Return from a signal handler, restoring the context before it.
+fn sys_sigreturn(tf: &mut TrapFrame) -> SysResult {
+    info!("sigreturn: pid: {}", thread::current().id());
+    if signal::sigreturn(tf).is_err() {
+        // the stack is broken, so we can't return to anywhere
+        signal::send_fault_signal(SIGSEGV);
+    }
+    // the return value is written to a0, keep the restored one
+    Ok(tf.x[10] as isize)
+}
+
+/// Change the blocked signals by `how` with `set` if it's not null.
+/// Store the old mask to `oldset` if it's not null.
+fn sys_sigprocmask(how: usize, set: *const usize, oldset: *mut usize) -> SysResult {
+    info!("sigprocmask: how: {}, set: {:?}, oldset: {:?}", how, set, oldset);
+    if !set.is_null() {
+        process().memory_set.lock().check_read_ptr(set)?;
+    }
+    if !oldset.is_null() {
+        process().memory_set.lock().check_write_ptr(oldset)?;
+    }
+    let old = process().sig_mask;
+    if !set.is_null() {
+        let set = unsafe { set.read() };
+        let mask = match how {
+            SIG_BLOCK => old | set,
+            SIG_UNBLOCK => old & !set,
+            SIG_SETMASK => set,
+            _ => return Err(SysError::Inval),
+        };
+        signal::set_mask(mask);
+    }
+    if !oldset.is_null() {
+        unsafe { oldset.write(old); }
+    }
+    Ok(0)
+}
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+/// Get the current process id, which is the id of its thread group
 fn sys_getpid() -> SysResult {
-    Ok(thread::current().id() as isize)
+    let tgid = processor().manager().get_tgid(thread::current().id()).unwrap();
+    Ok(tgid as isize)
 }
 
-/// Exit the current process
+/// Exit the current thread
 fn sys_exit(exit_code: isize) -> SysResult {
     let pid = thread::current().id();
     info!("exit: {}, code: {}", pid, exit_code);
-    processor().manager().exit(pid, exit_code as usize);
+    processor().manager().exit(pid, exit_status(exit_code as usize));
     processor().yield_now();
     unreachable!();
 }
@@ -273,13 +660,193 @@ fn sys_lab6_set_priority(priority: usize) -> SysResult {
     Ok(0)
 }
 
+/// Map `len` bytes of anonymous memory. Return the start address.
+/// Pages are allocated lazily on the first access.
+fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> SysResult {
+    let prot = MmapProt::from_bits_truncate(prot);
+    let flags = MmapFlags::from_bits_truncate(flags);
+    info!("mmap: addr: {:#x}, len: {:#x}, prot: {:?}, flags: {:?}, fd: {}, offset: {:#x}", addr, len, prot, flags, fd, offset);
+    if !flags.contains(MmapFlags::ANONYMOUS) {
+        return Err(SysError::Unimp);
+    }
+    if len == 0 || addr % PAGE_SIZE != 0 || !prot.is_wx_exclusive() {
+        return Err(SysError::Inval);
+    }
+    let len = len.checked_add(PAGE_SIZE - 1).ok_or(SysError::Nomem)? / PAGE_SIZE * PAGE_SIZE;
+    let proc = process();
+    let mut heap = proc.heap.lock();
+    let mut memory_set = proc.memory_set.lock();
+    let start = if flags.contains(MmapFlags::FIXED) {
+        if addr == 0 || addr.checked_add(len).map_or(true, |end| end > USER_STACK_OFFSET) {
+            return Err(SysError::Inval);
+        }
+        memory_set.remove(addr, addr + len);
+        heap.unmap(addr, addr + len);
+        addr
+    } else {
+        let hint = if addr == 0 { USER_MMAP_OFFSET } else { addr };
+        memory_set.find_free_area(hint, len).ok_or(SysError::Nomem)?
+    };
+    if start + len > USER_STACK_OFFSET {
+        return Err(SysError::Nomem);
+    }
+    memory_set.push(start, start + len, prot.to_attr(), Delay::new(GlobalFrameAlloc), "mmap");
+    Ok(start as isize)
+}
+
+/// Unmap the pages in `[addr, addr + len)`.
+/// Areas partially covered by the range are shrunk or split.
+fn sys_munmap(addr: usize, len: usize) -> SysResult {
+    info!("munmap: addr: {:#x}, len: {:#x}", addr, len);
+    if len == 0 || addr % PAGE_SIZE != 0 {
+        return Err(SysError::Inval);
+    }
+    let len = len.checked_add(PAGE_SIZE - 1).ok_or(SysError::Inval)? / PAGE_SIZE * PAGE_SIZE;
+    let end = addr.checked_add(len).ok_or(SysError::Inval)?;
+    let proc = process();
+    let mut heap = proc.heap.lock();
+    proc.memory_set.lock().remove(addr, end);
+    heap.unmap(addr, end);
+    Ok(0)
+}
+
+/// Change the access protection of the pages in `[addr, addr + len)`.
+fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult {
+    let prot = MmapProt::from_bits_truncate(prot);
+    info!("mprotect: addr: {:#x}, len: {:#x}, prot: {:?}", addr, len, prot);
+    if addr % PAGE_SIZE != 0 || !prot.is_wx_exclusive() {
+        return Err(SysError::Inval);
+    }
+    let len = len.checked_add(PAGE_SIZE - 1).ok_or(SysError::Inval)? / PAGE_SIZE * PAGE_SIZE;
+    let end = addr.checked_add(len).ok_or(SysError::Inval)?;
+    // only the user's own areas can be changed, other pages are taken as unmapped
+    process().memory_set.lock().protect(addr, end, prot.to_attr()).map_err(|_| SysError::Nomem)?;
+    Ok(0)
+}
+
+/// Set the end of the heap to `addr`. Return the new end, or the current one if it fails.
+/// Pages in the heap are allocated lazily on the first access.
+fn sys_brk(addr: usize) -> SysResult {
+    info!("brk: addr: {:#x}", addr);
+    let proc = process();
+    let mut heap = proc.heap.lock();
+    if heap.start == 0 || addr < heap.start {
+        return Ok(heap.end as isize);
+    }
+    let old_top = (heap.end + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+    let new_top = match addr.checked_add(PAGE_SIZE - 1) {
+        Some(addr) => addr / PAGE_SIZE * PAGE_SIZE,
+        None => return Ok(heap.end as isize),
+    };
+    let mut memory_set = proc.memory_set.lock();
+    if new_top > old_top {
+        // the heap can't grow into other areas
+        if new_top > USER_STACK_OFFSET || memory_set.find_free_area(old_top, new_top - old_top) != Some(old_top) {
+            return Ok(heap.end as isize);
+        }
+        // grow the heap area in place, or make it if the heap is empty
+        if old_top == heap.start || memory_set.grow(old_top, new_top).is_err() {
+            memory_set.push(old_top, new_top, MemoryAttr::default().user(), Delay::new(GlobalFrameAlloc), "heap");
+        }
+    } else if new_top < old_top {
+        memory_set.remove(new_top, old_top);
+    }
+    heap.end = addr;
+    Ok(addr as isize)
+}
+
 fn sys_putc(c: char) -> SysResult {
     print!("{}", c);
     Ok(0)
 }
 
-fn get_file(fd: usize) -> Result<&'static Arc<Mutex<File>>, SysError> {
-    process().files.get(&fd).ok_or(SysError::Inval)
+/// Put `desc` at the lowest free fd. Return the fd.
+fn add_file(desc: FileDesc) -> usize {
+    let mut files = process().files.lock();
+    let fd = (3..).find(|i| !files.contains_key(i)).unwrap();
+    files.insert(fd, desc);
+    fd
+}
+
+/// Open `inode` at the lowest free fd. Return the fd.
+fn add_inode(inode: Arc<INode>, options: OpenOptions) -> usize {
+    let file = Arc::new(Mutex::new(FileHandle::new(inode, options)));
+    add_file(FileDesc { file, cloexec: false })
+}
+
+fn get_file(fd: usize) -> Result<Arc<Mutex<FileHandle>>, SysError> {
+    process().files.lock().get(&fd).map(|desc| desc.file.clone()).ok_or(SysError::Inval)
+}
+
+/// Check a user C string at `ptr` and copy it into the kernel.
+/// The memory set is locked while copying, so the pages of the string are faulted in first,
+/// with the memory set locked only while checking each page.
+#[cfg(not(feature = "no_mmu"))]
+fn check_and_clone_cstr(ptr: *const u8) -> Result<String, SysError> {
+    let mut addr = ptr as usize;
+    loop {
+        let len = PAGE_SIZE - addr % PAGE_SIZE;
+        process().memory_set.lock().check_read_array(addr as *const u8, len)?;
+        let page = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+        if page.contains(&0) {
+            break;
+        }
+        addr += len;
+    }
+    Ok(process().memory_set.lock().check_and_clone_cstr(ptr)?)
+}
+
+/// Copy a C string at `ptr` into the kernel. Without MMU every address is accessible.
+#[cfg(feature = "no_mmu")]
+fn check_and_clone_cstr(ptr: *const u8) -> Result<String, SysError> {
+    let len = (0usize..).find(|&i| unsafe { *ptr.add(i) } == 0).unwrap();
+    let bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    String::from_utf8(bytes.to_vec()).map_err(|_| SysError::Inval)
+}
+
+/// Copy a NULL-terminated array of user C strings at `ptr` into the kernel. A null `ptr` is empty.
+fn check_and_clone_cstr_array(ptr: *const *const u8) -> Result<Vec<String>, SysError> {
+    let mut strs = Vec::new();
+    if ptr.is_null() {
+        return Ok(strs);
+    }
+    for i in 0.. {
+        let item = ptr.wrapping_add(i);
+        process().memory_set.lock().check_read_ptr(item)?;
+        let str_ptr = unsafe { item.read() };
+        if str_ptr.is_null() {
+            break;
+        }
+        strs.push(check_and_clone_cstr(str_ptr)?);
+    }
+    Ok(strs)
+}
+
+/// Find the inode of `path`, which may be relative to the working directory.
+fn lookup_inode(path: &str) -> Result<Arc<INode>, SysError> {
+    if path.is_empty() {
+        return Err(SysError::Noent);
+    }
+    let cwd = process().cwd.lock().inode.clone();
+    Ok(crate::fs::lookup(&cwd, path)?)
+}
+
+/// Find the inode of the parent directory of `path`, and the file name in it.
+fn lookup_parent(path: &str) -> Result<(Arc<INode>, String), SysError> {
+    if path.is_empty() {
+        return Err(SysError::Noent);
+    }
+    let path = path.trim_end_matches('/');
+    let (dir_path, name) = match path.rfind('/') {
+        Some(pos) => (&path[..pos + 1], &path[pos + 1..]),
+        None => (".", path),
+    };
+    // the root directory has no parent, and `.` and `..` can't be created or removed
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(SysError::Inval);
+    }
+    let dir_inode = lookup_inode(dir_path)?;
+    Ok((dir_inode, String::from(name)))
 }
 
 pub type SysResult = Result<isize, SysError>;
@@ -292,15 +859,21 @@ pub enum SysError {
     // name conversion E_XXXXX -> SysError::Xxxxx
     // see https://github.com/oscourse-tsinghua/ucore_os_lab/blob/master/labcodes/lab8/libs/error.h
     // we only add current used errors here
+    BadProc = 2,// Invalid process, also no child to wait
     Inval = 3,// Invalid argument, also Invaild fd number.
     Nomem = 4,// Out of memory, also used as no device space in ucore
+    NoFreeProc = 5,// Process number exceeded, try again later
+    Fault = 6,// Memory access fault, e.g. a bad user pointer
+    InvalElf = 8,// Invalid or unsupported ELF file
     Noent = 16,// No such file or directory
     Isdir = 17,// Fd is a directory
     Notdir = 18,// Fd is not a directory
     Xdev = 19,// Cross-device link
     Unimp = 20,// Not implemented
+    Seek = 21,// Illegal seek, e.g. on a pipe
     Exists = 23,// File exists
     Notempty = 24,// Directory is not empty
+    Pipe = 25,// Broken pipe, the read end is closed
 
     #[allow(dead_code)]
     Unspcified = 1,// A really really unknown error.
@@ -325,6 +898,24 @@ impl From<FsError> for SysError {
     }
 }
 
+impl From<VMError> for SysError {
+    fn from(error: VMError) -> Self {
+        match error {
+            VMError::InvalidPtr => SysError::Fault,
+            VMError::InvalidUtf8 => SysError::Inval,
+        }
+    }
+}
+
+impl From<KernelStackError> for SysError {
+    fn from(error: KernelStackError) -> Self {
+        match error {
+            KernelStackError::NoSlot => SysError::NoFreeProc,
+            KernelStackError::NoMemory => SysError::Nomem,
+        }
+    }
+}
+
 bitflags! {
     struct VfsFlags: usize {
         // WARNING: different from origin uCore
@@ -338,6 +929,8 @@ bitflags! {
         const TRUNCATE = 1 << 4;
         /// append on each write
         const APPEND = 1 << 5;
+        /// close the file on exec
+        const CLOEXEC = 1 << 6;
     }
 }
 
@@ -348,6 +941,52 @@ impl VfsFlags {
     }
 }
 
+bitflags! {
+    struct WaitOptions: usize {
+        /// Return at once if no child has exited
+        const NOHANG = 1 << 0;
+    }
+}
+
+bitflags! {
+    struct MmapProt: usize {
+        /// Data can be read
+        const READ = 1 << 0;
+        /// Data can be written
+        const WRITE = 1 << 1;
+        /// Data can be executed
+        const EXEC = 1 << 2;
+    }
+}
+
+impl MmapProt {
+    /// W^X: the pages can't be both writable and executable
+    fn is_wx_exclusive(self) -> bool {
+        !self.contains(MmapProt::WRITE | MmapProt::EXEC)
+    }
+    fn to_attr(self) -> MemoryAttr {
+        let mut attr = MemoryAttr::default().user();
+        // PROT_NONE: the pages are kept, but the user can't access them
+        if self.is_empty() { attr = attr.hide(); }
+        if !self.contains(MmapProt::WRITE) { attr = attr.readonly(); }
+        if self.contains(MmapProt::EXEC) { attr = attr.execute(); }
+        attr
+    }
+}
+
+bitflags! {
+    struct MmapFlags: usize {
+        /// Changes are shared
+        const SHARED = 1 << 0;
+        /// Changes are private
+        const PRIVATE = 1 << 1;
+        /// Place the mapping at the exact address
+        const FIXED = 1 << 4;
+        /// The mapping is not backed by any file
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
 #[repr(C)]
 struct DirEntry {
     offset: u32,
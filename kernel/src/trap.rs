@@ -8,6 +8,7 @@ pub static mut TICK: usize = 0;
 pub fn timer() {
     if cpu::id() == 0 {
         unsafe { TICK += 1; }
+        crate::swap::tick();
     }
     processor().tick();
 }
@@ -17,11 +18,21 @@ pub fn error(tf: &TrapFrame) -> ! {
     let pid = processor().pid();
     error!("On CPU{} Process {}", cpu::id(), pid);
 
-    processor().manager().exit(pid, 0x100);
+    processor().manager().exit_group(pid, signal_status(crate::signal::SIGKILL));
     processor().yield_now();
     unreachable!();
 }
 
+/// Handle a fault by sending signal `sig` to the current thread if it's from user mode.
+/// A fault in kernel mode is fatal to the process.
+pub fn fault(tf: &TrapFrame, sig: usize) {
+    if !tf.is_user() {
+        error(tf);
+    }
+    warn!("Process {} fault, send signal {}: {:#x?}", processor().pid(), sig, tf);
+    crate::signal::send_fault_signal(sig);
+}
+
 pub fn serial(c: char) {
     crate::fs::STDIN.push(c);
 }
\ No newline at end of file
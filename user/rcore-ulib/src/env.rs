@@ -0,0 +1,82 @@
+//! The args and environment variables of the process
+//!
+//! They are found on the initial stack, where there are argc, argv, NULL, envp, NULL from the stack pointer up.
+
+use core::{ptr, slice, str};
+
+static mut ARGV: *const *const u8 = ptr::null();
+static mut ENVP: *const *const u8 = ptr::null();
+
+/// Find the args and environment variables from the initial stack pointer `sp`
+pub(crate) unsafe fn init(sp: *const usize) {
+    let argc = *sp;
+    ARGV = sp.add(1) as *const *const u8;
+    ENVP = ARGV.add(argc + 1);
+}
+
+/// The args of the process, starting with the program name
+pub fn args() -> Args {
+    Args(CStrs(unsafe { ARGV }))
+}
+
+/// The environment variables of the process as (key, value) pairs
+pub fn env() -> Vars {
+    Vars(CStrs(unsafe { ENVP }))
+}
+
+/// Get the environment variable `key`
+pub fn var(key: &str) -> Option<&'static str> {
+    env().find(|&(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// The NULL-terminated environment array, which can be passed to `sys_exec`
+pub fn environ() -> *const *const u8 {
+    unsafe { ENVP }
+}
+
+/// Iterator over a NULL-terminated array of C strings
+struct CStrs(*const *const u8);
+
+impl Iterator for CStrs {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        if self.0.is_null() {
+            return None;
+        }
+        unsafe {
+            let s = *self.0;
+            if s.is_null() {
+                return None;
+            }
+            self.0 = self.0.add(1);
+            let len = (0..).find(|&i| *s.add(i) == 0).unwrap();
+            Some(str::from_utf8(slice::from_raw_parts(s, len)).unwrap_or(""))
+        }
+    }
+}
+
+/// Iterator over the args, created by `args`
+pub struct Args(CStrs);
+
+impl Iterator for Args {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        self.0.next()
+    }
+}
+
+/// Iterator over the environment variables, created by `env`
+pub struct Vars(CStrs);
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+
+    fn next(&mut self) -> Option<(&'static str, &'static str)> {
+        self.0.next().map(|var| match var.find('=') {
+            Some(pos) => (&var[..pos], &var[pos + 1..]),
+            None => (var, ""),
+        })
+    }
+}
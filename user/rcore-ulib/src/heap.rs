@@ -0,0 +1,165 @@
+//! The heap of the process, grown by `sys_brk` when it's full
+//!
+//! The free memory is a list of holes sorted by address, and a block is allocated from the first hole large enough.
+//! A freed block is merged with the holes next to it.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::syscall::sys_brk;
+
+/// The heap grows by at least this size
+const HEAP_GROW_SIZE: usize = 0x4000;
+
+/// A free block, with the header stored at its start
+struct Hole {
+    size: usize,
+    next: *mut Hole,
+}
+
+/// Blocks are aligned to and sized in units, so that a hole header fits in any space left
+const UNIT: usize = size_of::<Hole>();
+
+struct Heap {
+    /// the hole of the lowest address
+    head: *mut Hole,
+    /// the end of the heap, 0 before it is first grown
+    top: usize,
+}
+
+pub struct BrkHeap {
+    locked: AtomicBool,
+    heap: UnsafeCell<Heap>,
+}
+
+// the heap is only accessed with the lock held
+unsafe impl Sync for BrkHeap {}
+
+impl BrkHeap {
+    pub const fn empty() -> Self {
+        BrkHeap {
+            locked: AtomicBool::new(false),
+            heap: UnsafeCell::new(Heap { head: ptr::null_mut(), top: 0 }),
+        }
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Heap) -> T) -> T {
+        while self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {}
+        let ret = f(unsafe { &mut *self.heap.get() });
+        self.locked.store(false, Ordering::Release);
+        ret
+    }
+}
+
+unsafe impl GlobalAlloc for BrkHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with(|heap| heap.alloc(&layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with(|heap| heap.free(ptr as usize, block_size(&layout)))
+    }
+}
+
+impl Heap {
+    unsafe fn alloc(&mut self, layout: &Layout) -> *mut u8 {
+        let size = block_size(layout);
+        let align = layout.align().max(UNIT);
+        loop {
+            if let Some(addr) = self.take(size, align) {
+                return addr as *mut u8;
+            }
+            if !self.grow(size + align) {
+                return ptr::null_mut();
+            }
+        }
+    }
+
+    /// Take a block of `size` aligned to `align` from the first hole large enough.
+    /// The space left before and after the block stays in the holes.
+    unsafe fn take(&mut self, size: usize, align: usize) -> Option<usize> {
+        let mut link: *mut *mut Hole = &mut self.head;
+        while !(*link).is_null() {
+            let hole = *link;
+            let hole_start = hole as usize;
+            let hole_end = hole_start + (*hole).size;
+            let start = align_up(hole_start, align);
+            if start < hole_end && hole_end - start >= size {
+                let end = start + size;
+                let mut next = (*hole).next;
+                if end < hole_end {
+                    next = write_hole(end, hole_end - end, next);
+                }
+                if start > hole_start {
+                    next = write_hole(hole_start, start - hole_start, next);
+                }
+                *link = next;
+                return Some(start);
+            }
+            link = &mut (*hole).next;
+        }
+        None
+    }
+
+    /// Grow the heap by at least `size` and add the new memory to the holes.
+    /// Return false if `sys_brk` fails.
+    unsafe fn grow(&mut self, size: usize) -> bool {
+        if self.top == 0 {
+            self.top = align_up(sys_brk(0), UNIT);
+        }
+        let size = align_up(size, UNIT).max(HEAP_GROW_SIZE);
+        let new_top = match self.top.checked_add(size) {
+            Some(new_top) => new_top,
+            None => return false,
+        };
+        if sys_brk(new_top) != new_top {
+            return false;
+        }
+        self.free(self.top, size);
+        self.top = new_top;
+        true
+    }
+
+    /// Put the block `[addr, addr + size)` into the holes
+    unsafe fn free(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut Hole = ptr::null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = (*next).next;
+        }
+        let mut size = size;
+        if !next.is_null() && addr + size == next as usize {
+            size += (*next).size;
+            next = (*next).next;
+        }
+        if !prev.is_null() && prev as usize + (*prev).size == addr {
+            (*prev).size += size;
+            (*prev).next = next;
+            return;
+        }
+        let hole = write_hole(addr, size, next);
+        match prev.is_null() {
+            true => self.head = hole,
+            false => (*prev).next = hole,
+        }
+    }
+}
+
+/// The size of the block allocated for `layout`
+fn block_size(layout: &Layout) -> usize {
+    align_up(layout.size().max(1), UNIT)
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// Write the header of a hole at `addr`
+unsafe fn write_hole(addr: usize, size: usize, next: *mut Hole) -> *mut Hole {
+    let hole = addr as *mut Hole;
+    hole.write(Hole { size, next });
+    hole
+}
@@ -73,4 +73,10 @@ pub const O_RDWR: usize = 2; // open for reading and writing
 pub const O_CREAT: usize = 0x00000004; // create file if it does not exist
 pub const O_EXCL: usize = 0x00000008; // error if O_CREAT and the file exists
 pub const O_TRUNC: usize = 0x00000010; // truncate file upon open
-pub const O_APPEND: usize = 0x00000020; // append on each write
\ No newline at end of file
+pub const O_APPEND: usize = 0x00000020; // append on each write
+pub const O_CLOEXEC: usize = 0x00000040; // close the file on exec
+
+/* whence for seek */
+pub const SEEK_SET: u8 = 0; // offset from the start of the file
+pub const SEEK_CUR: u8 = 1; // offset from the current position
+pub const SEEK_END: u8 = 2; // offset from the end of the file
@@ -31,8 +31,32 @@ fn initfd(fd2: usize, path: &str, open_flags: usize) -> i32 {
     return ret;
 }
 
+// The entry. Pass the initial stack pointer, where the args are, to `rust_start`.
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+global_asm!("
+    .globl _start
+_start:
+    mv a0, sp
+    call rust_start
+");
+#[cfg(target_arch = "x86_64")]
+global_asm!("
+    .globl _start
+_start:
+    mov %rsp, %rdi
+    call rust_start
+");
+#[cfg(target_arch = "aarch64")]
+global_asm!("
+    .globl _start
+_start:
+    mov x0, sp
+    bl rust_start
+");
+
 #[no_mangle]
-pub extern "C" fn _start(_argc: isize, _argv: *const *const u8) -> ! {
+pub extern "C" fn rust_start(sp: *const usize) -> ! {
+    unsafe { crate::env::init(sp); }
     let fd = initfd(STDIN, "stdin:", O_RDONLY);
     if fd < 0 {
         panic!("open <stdin> failed: {}.", fd);
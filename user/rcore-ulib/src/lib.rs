@@ -1,5 +1,6 @@
 #![no_std]
 #![feature(asm)]
+#![feature(global_asm)]
 #![feature(lang_items)]
 #![feature(panic_info_message)]
 #![feature(linkage)]
@@ -8,4 +9,10 @@
 #[macro_use]
 pub mod io;
 pub mod syscall;
-pub mod lang_items;
\ No newline at end of file
+pub mod lang_items;
+pub mod env;
+pub mod heap;
+
+/// It should be defined in heap mod, but in Rust `global_allocator` must be in root mod.
+#[global_allocator]
+static HEAP_ALLOCATOR: heap::BrkHeap = heap::BrkHeap::empty();
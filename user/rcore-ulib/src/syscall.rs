@@ -32,8 +32,10 @@ pub fn sys_exit(code: usize) -> ! {
 }
 
 
-pub fn sys_exec(name: *const u8, argc: usize, argv: *const *const u8) -> i32 {
-    sys_call(SyscallId::Exec, name as usize, argc, argv as usize, 0, 0, 0)
+/// Run the program at `name` with `argc` args in `argv`.
+/// `envp` is a NULL-terminated array of "KEY=VALUE" strings, or null for no environment variable.
+pub fn sys_exec(name: *const u8, argc: usize, argv: *const *const u8, envp: *const *const u8) -> i32 {
+    sys_call(SyscallId::Exec, name as usize, argc, argv as usize, envp as usize, 0, 0)
 }
 
 pub fn sys_write(fd: usize, base: *const u8, len: usize) -> i32 {
@@ -45,13 +47,66 @@ pub fn sys_read(fd: usize, base: *mut u8, len: usize) -> i32 {
 }
 
 pub fn sys_open(path: &str, flags: usize) -> i32 {
-    // UNSAFE: append '\0' to the string
-    use core::mem::replace;
-    let end = unsafe { &mut *(path.as_ptr().offset(path.len() as isize) as *mut u8) };
-    let backup = replace(end, 0);
-    let ret = sys_call(SyscallId::Open, path.as_ptr() as usize, flags, 0, 0, 0, 0);
-    *end = backup;
-    ret
+    with_cstr(path, |path| sys_call(SyscallId::Open, path as usize, flags, 0, 0, 0, 0))
+}
+
+/// Copy the current working directory to `buf` as a C string
+pub fn sys_getcwd(buf: &mut [u8]) -> i32 {
+    sys_call(SyscallId::GetCwd, buf.as_mut_ptr() as usize, buf.len(), 0, 0, 0, 0)
+}
+
+/// Change the current working directory
+pub fn sys_chdir(path: &str) -> i32 {
+    with_cstr(path, |path| sys_call(SyscallId::Chdir, path as usize, 0, 0, 0, 0, 0))
+}
+
+/// Create a directory
+pub fn sys_mkdir(path: &str) -> i32 {
+    with_cstr(path, |path| sys_call(SyscallId::Mkdir, path as usize, 0, 0, 0, 0, 0))
+}
+
+/// Remove an empty directory
+pub fn sys_rmdir(path: &str) -> i32 {
+    with_cstr(path, |path| sys_call(SyscallId::Rmdir, path as usize, 0, 0, 0, 0, 0))
+}
+
+/// Make a new name for a file
+pub fn sys_link(old_path: &str, new_path: &str) -> i32 {
+    with_cstr(old_path, |old_path| with_cstr(new_path, |new_path| {
+        sys_call(SyscallId::Link, old_path as usize, new_path as usize, 0, 0, 0, 0)
+    }))
+}
+
+/// Remove a name of a file
+pub fn sys_unlink(path: &str) -> i32 {
+    with_cstr(path, |path| sys_call(SyscallId::Unlink, path as usize, 0, 0, 0, 0, 0))
+}
+
+/// Move a file to another path
+pub fn sys_rename(old_path: &str, new_path: &str) -> i32 {
+    with_cstr(old_path, |old_path| with_cstr(new_path, |new_path| {
+        sys_call(SyscallId::Rename, old_path as usize, new_path as usize, 0, 0, 0, 0)
+    }))
+}
+
+const PATH_MAX: usize = 256;
+
+/// Copy `path` to a '\0' terminated buffer, and call `f` with the buffer
+fn with_cstr<T>(path: &str, f: impl FnOnce(*const u8) -> T) -> T {
+    let mut buf = [0u8; PATH_MAX];
+    assert!(path.len() < PATH_MAX, "path is too long");
+    buf[..path.len()].copy_from_slice(path.as_bytes());
+    f(buf.as_ptr())
+}
+
+/// Move the offset of the file. Return the new offset.
+pub fn sys_seek(fd: usize, offset: isize, whence: u8) -> i32 {
+    sys_call(SyscallId::Seek, fd, offset as usize, whence as usize, 0, 0, 0)
+}
+
+/// Write the data of the file to the device
+pub fn sys_fsync(fd: usize) -> i32 {
+    sys_call(SyscallId::Fsync, fd, 0, 0, 0, 0, 0)
 }
 
 pub fn sys_close(fd: usize) -> i32 {
@@ -62,24 +117,205 @@ pub fn sys_dup(fd1: usize, fd2: usize) -> i32 {
     sys_call(SyscallId::Dup, fd1, fd2, 0, 0, 0, 0)
 }
 
+/// Create a pipe. Store the read fd and the write fd to `fds`.
+pub fn sys_pipe(fds: &mut [u32; 2]) -> i32 {
+    sys_call(SyscallId::Pipe, fds.as_mut_ptr() as usize, 0, 0, 0, 0, 0)
+}
+
 /// Fork the current process. Return the child's PID.
 pub fn sys_fork() -> i32 {
     sys_call(SyscallId::Fork, 0, 0, 0, 0, 0, 0)
 }
 
-/// Wait the process exit.
-/// Return the PID. Store exit code to `code` if it's not null.
-pub fn sys_wait(pid: usize, code: *mut i32) -> i32 {
-    sys_call(SyscallId::Wait, pid, code as usize, 0, 0, 0, 0)
+pub const CLONE_VM: usize = 0x100; // share the memory
+pub const CLONE_FS: usize = 0x200; // share the working directory
+pub const CLONE_FILES: usize = 0x400; // share the file descriptor table
+pub const CLONE_SIGHAND: usize = 0x800; // share the signal actions
+pub const CLONE_THREAD: usize = 0x10000; // same thread group
+
+/// Create a new thread sharing the resources selected by `flags`, which calls `f(arg)`.
+/// The new thread runs on the stack `stack`. It can be 0 only without `CLONE_VM`,
+/// then the new thread runs on the caller's stack in its own copy of the memory.
+/// Return the new thread's PID.
+pub fn sys_clone(flags: usize, stack: usize, f: extern fn(usize) -> !, arg: usize) -> i32 {
+    unsafe { __clone(flags, stack, f, arg) }
+}
+
+// The new thread can't return from `sys_clone` on a new stack, so call `f` right after the syscall.
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+global_asm!("
+    .globl __clone
+__clone:
+    mv t0, a2
+    mv t1, a3
+    mv a2, a1
+    mv a1, a0
+    li a0, 5
+    ecall
+    bnez a0, 1f
+    mv a0, t1
+    jalr t0
+1:
+    ret
+");
+#[cfg(target_arch = "x86_64")]
+global_asm!("
+    .globl __clone
+__clone:
+    mov %rdx, %r10
+    mov %rcx, %r11
+    mov $5, %rax
+    int $0x40
+    test %rax, %rax
+    jnz 1f
+    mov %r11, %rdi
+    call *%r10
+1:
+    ret
+");
+#[cfg(target_arch = "aarch64")]
+global_asm!("
+    .globl __clone
+__clone:
+    mov x10, x2
+    mov x11, x3
+    mov x8, 5
+    svc 0
+    cbnz x0, 1f
+    mov x0, x11
+    blr x10
+1:
+    ret
+");
+
+extern {
+    fn __clone(flags: usize, stack: usize, f: extern fn(usize) -> !, arg: usize) -> i32;
+}
+
+pub const WNOHANG: usize = 1; // return at once if no child has exited
+
+/// Wait for the child process `pid` to exit, or any child if `pid` is 0.
+/// Return its PID, or 0 if no child has exited with `WNOHANG`.
+/// Store its exit status to `status` if it's not null.
+pub fn sys_wait(pid: usize, status: *mut i32, options: usize) -> i32 {
+    sys_call(SyscallId::Wait, pid, status as usize, options, 0, 0, 0)
+}
+
+/// Whether the child exited normally, according to the status from `sys_wait`
+pub fn wifexited(status: i32) -> bool {
+    status & 0x7f == 0
+}
+
+/// The exit code of a child which exited normally
+pub fn wexitstatus(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+
+/// Whether the child was terminated by a signal
+pub fn wifsignaled(status: i32) -> bool {
+    status & 0x7f != 0
+}
+
+/// The signal which terminated the child
+pub fn wtermsig(status: i32) -> i32 {
+    status & 0x7f
 }
 
 pub fn sys_yield() -> i32 {
     sys_call(SyscallId::Yield, 0, 0, 0, 0, 0, 0)
 }
 
-/// Kill the process
-pub fn sys_kill(pid: usize) -> i32 {
-    sys_call(SyscallId::Kill, pid, 0, 0, 0, 0, 0)
+/// Send signal `sig` to the process `pid`
+pub fn sys_kill(pid: usize, sig: usize) -> i32 {
+    sys_call(SyscallId::Kill, pid, sig, 0, 0, 0, 0)
+}
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGABRT: usize = 6;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+
+pub const SIG_DFL: usize = 0; // default action
+pub const SIG_IGN: usize = 1; // ignore the signal
+
+pub const SA_NODEFER: usize = 0x40000000; // don't block the signal in its handler
+pub const SA_RESETHAND: usize = 0x80000000; // reset to default when the handler is called
+
+pub const SIG_BLOCK: usize = 0; // block the signals in the set
+pub const SIG_UNBLOCK: usize = 1; // unblock the signals in the set
+pub const SIG_SETMASK: usize = 2; // set the mask to the set
+
+/// The action to take when a signal is delivered
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN` or the address of `extern fn(sig: usize)`
+    pub handler: usize,
+    pub flags: usize,
+    /// Signals to block in the handler
+    pub mask: usize,
+    /// Where the handler returns to. Filled by `sys_sigaction` if it's 0.
+    pub restorer: usize,
+}
+
+// Return from a signal handler to the interrupted context
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+global_asm!("
+    .globl __sigreturn
+__sigreturn:
+    li a0, 14
+    ecall
+");
+#[cfg(target_arch = "x86_64")]
+global_asm!("
+    .globl __sigreturn
+__sigreturn:
+    mov $14, %rax
+    int $0x40
+");
+#[cfg(target_arch = "aarch64")]
+global_asm!("
+    .globl __sigreturn
+__sigreturn:
+    mov x8, 14
+    svc 0
+");
+
+extern {
+    fn __sigreturn();
+}
+
+/// Set the action of signal `sig` to `act` if it's not null.
+/// Store the old action to `oldact` if it's not null.
+pub fn sys_sigaction(sig: usize, act: *const SigAction, oldact: *mut SigAction) -> i32 {
+    let mut new_act;
+    let act = match act.is_null() {
+        true => act,
+        false => {
+            new_act = unsafe { act.read() };
+            if new_act.restorer == 0 {
+                new_act.restorer = __sigreturn as usize;
+            }
+            &new_act as *const SigAction
+        }
+    };
+    sys_call(SyscallId::SigAction, sig, act as usize, oldact as usize, 0, 0, 0)
+}
+
+/// Change the blocked signals by `how` with `set` if it's not null.
+/// Store the old mask to `oldset` if it's not null.
+pub fn sys_sigprocmask(how: usize, set: *const usize, oldset: *mut usize) -> i32 {
+    sys_call(SyscallId::SigProcMask, how, set as usize, oldset as usize, 0, 0, 0)
 }
 
 /// Get the current process id
@@ -103,6 +339,37 @@ pub fn sys_putc(c: u8) -> i32 {
     sys_call(SyscallId::Putc, c as usize, 0, 0, 0, 0, 0)
 }
 
+pub const PROT_NONE: usize = 0x0; // page can not be accessed
+pub const PROT_READ: usize = 0x1; // page can be read
+pub const PROT_WRITE: usize = 0x2; // page can be written
+pub const PROT_EXEC: usize = 0x4; // page can be executed
+
+pub const MAP_SHARED: usize = 0x01; // share changes
+pub const MAP_PRIVATE: usize = 0x02; // changes are private
+pub const MAP_FIXED: usize = 0x10; // interpret addr exactly
+pub const MAP_ANONYMOUS: usize = 0x20; // don't use a file
+
+/// Map `len` bytes of memory. Return the start address, or a negative error code.
+pub fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> isize {
+    sys_call(SyscallId::Mmap, addr, len, prot, flags, fd, offset) as isize
+}
+
+/// Change the access protection of the pages in `[addr, addr + len)`
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> i32 {
+    sys_call(SyscallId::Mprotect, addr, len, prot, 0, 0, 0)
+}
+
+/// Unmap the pages in `[addr, addr + len)`
+pub fn sys_munmap(addr: usize, len: usize) -> i32 {
+    sys_call(SyscallId::Munmap, addr, len, 0, 0, 0, 0)
+}
+
+/// Set the end of the heap to `addr`. Return the new end, which is the current one if it fails.
+/// `sys_brk(0)` gets the current end.
+pub fn sys_brk(addr: usize) -> usize {
+    sys_call(SyscallId::Brk, addr, 0, 0, 0, 0, 0) as usize
+}
+
 #[allow(dead_code)]
 enum SyscallId{
     Exit = 1,
@@ -113,11 +380,16 @@ enum SyscallId{
     Yield = 10,
     Sleep = 11,
     Kill = 12,
+    SigAction = 13,
+    SigReturn = 14,
+    SigProcMask = 15,
     GetTime = 17,
     GetPid = 18,
     Mmap = 20,
     Munmap = 21,
     Shmem = 22,
+    Mprotect = 23,
+    Brk = 24,
     Putc = 30,
     Pgdir = 31,
     Open = 100,
@@ -127,8 +399,15 @@ enum SyscallId{
     Seek = 104,
     Fstat = 110,
     Fsync = 111,
+    Chdir = 120,
     GetCwd = 121,
+    Mkdir = 122,
+    Rmdir = 123,
+    Link = 124,
+    Unlink = 125,
+    Rename = 126,
     GetDirEntry = 128,
     Dup = 130,
+    Pipe = 140,
     Lab6SetPriority = 255,
 }
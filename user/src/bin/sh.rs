@@ -4,7 +4,8 @@
 #[macro_use]
 extern crate rcore_ulib;
 use rcore_ulib::io::getc;
-use rcore_ulib::syscall::{sys_exec, sys_fork, sys_wait};
+use rcore_ulib::env::environ;
+use rcore_ulib::syscall::{sys_exec, sys_fork, sys_wait, wifexited, wexitstatus, wtermsig};
 
 pub fn get_line(buffer: &mut [u8]) -> usize {
     let mut pos: usize = 0;
@@ -101,13 +102,17 @@ pub fn main() -> i32 {
                     for i in 0..offset_pos {
                         ptrs[i] = unsafe { parsed.as_ptr().offset(offset[i] as isize) };
                     }
-                    return sys_exec(parsed.as_ptr(), offset_pos, ptrs.as_ptr());
+                    return sys_exec(parsed.as_ptr(), offset_pos, ptrs.as_ptr(), environ());
                 } else if pid < 0 {
-                    panic!("pid<0")
+                    println!("fork failed: {}", pid);
                 } else {
-                    let mut code: i32 = unsafe { uninitialized() };
-                    sys_wait(pid as usize, &mut code as *mut i32);
-                    println!("\n[Process exited with code {}]", code);
+                    let mut status: i32 = unsafe { uninitialized() };
+                    sys_wait(pid as usize, &mut status as *mut i32, 0);
+                    if wifexited(status) {
+                        println!("\n[Process exited with code {}]", wexitstatus(status));
+                    } else {
+                        println!("\n[Process killed by signal {}]", wtermsig(status));
+                    }
                 }
             }
         }